@@ -0,0 +1,334 @@
+use crate::{
+    indirect::{self, DispatchArgs},
+    pipeline, renderer,
+    shader::{Defines, ShaderLibrary},
+};
+
+/// threads per workgroup of the row passes in adaptive.wgsl
+const ROW_GROUP_SIZE: u32 = 64;
+/// threads per workgroup of the mark pass in adaptive.wgsl
+const PIXEL_GROUP_SIZE: u32 = 16;
+
+/// constants of adaptive.wgsl
+pub(crate) fn defines() -> Defines {
+    let [x, y, z] = renderer::MEGAKERNEL_GROUP_SIZE;
+    Defines::new()
+        .set_u32("COMPUTE_GROUP_SIZE", x * y * z)
+        .set_u32("PIXEL_GROUP_SIZE", PIXEL_GROUP_SIZE)
+        .set_u32("ROW_GROUP_SIZE", ROW_GROUP_SIZE)
+}
+
+/// Uniforms of adaptive.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AdaptiveUniforms {
+    threshold: f32,
+    min_samples: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Finds the pixels whose noise is above a threshold and compacts them into a list,
+/// the compute pass is then dispatched indirectly for these pixels only
+pub struct AdaptiveSampler {
+    mark_pipeline: wgpu::ComputePipeline,
+    count_pipeline: wgpu::ComputePipeline,
+    scan_pipeline: wgpu::ComputePipeline,
+    compact_pipeline: wgpu::ComputePipeline,
+
+    uniforms: AdaptiveUniforms,
+    uniform_buffer: wgpu::Buffer,
+
+    layout: wgpu::BindGroupLayout,
+    list_layout: wgpu::BindGroupLayout,
+    buffers: AdaptiveBuffers,
+}
+
+/// size dependent resources of the adaptive sampler
+struct AdaptiveBuffers {
+    // only used through the bind group
+    _mask: wgpu::Buffer,
+    _row_offsets: wgpu::Buffer,
+    pixel_list: wgpu::Buffer,
+    dispatch_args: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// binds the pixel list to the compute pass
+    list_bind_group: wgpu::BindGroup,
+}
+
+fn storage_buffer(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStage::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+impl AdaptiveSampler {
+    /// `frame_layout` is the compute layout of the frame buffer
+    pub fn new(
+        device: &wgpu::Device,
+        frame_layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("adaptive layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_buffer(1, false),
+                storage_buffer(2, false),
+                storage_buffer(3, false),
+                storage_buffer(4, false),
+            ],
+        });
+        let list_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("pixel list layout"),
+            entries: &[storage_buffer(0, true)],
+        });
+
+        let uniforms = AdaptiveUniforms {
+            threshold: 0.,
+            min_samples: 0,
+            width,
+            height,
+        };
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Adaptive Uniform Buffer"),
+            size: std::mem::size_of::<AdaptiveUniforms>() as u64,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let library = ShaderLibrary::builtin();
+        let pipeline = |entry_point, label| {
+            pipeline::create_compute_pipeline_with_entry(
+                device,
+                &[frame_layout, &layout],
+                library
+                    .module("adaptive_shader", "adaptive.wgsl", &defines())
+                    .expect("invalid adaptive shader"),
+                entry_point,
+                Some(label),
+            )
+        };
+        let mark_pipeline = pipeline("mark", "AdaptiveMarkPipeline");
+        let count_pipeline = pipeline("count_rows", "AdaptiveCountPipeline");
+        let scan_pipeline = pipeline("scan", "AdaptiveScanPipeline");
+        let compact_pipeline = pipeline("compact", "AdaptiveCompactPipeline");
+
+        let buffers = AdaptiveBuffers::new(
+            device,
+            &layout,
+            &list_layout,
+            &uniform_buffer,
+            width,
+            height,
+        );
+
+        Self {
+            mark_pipeline,
+            count_pipeline,
+            scan_pipeline,
+            compact_pipeline,
+
+            uniforms,
+            uniform_buffer,
+
+            layout,
+            list_layout,
+            buffers,
+        }
+    }
+
+    /// layout of `list_bind_group`
+    pub fn list_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.list_layout
+    }
+
+    /// binds the pixel list which is read by the compute pass
+    pub fn list_bind_group(&self) -> &wgpu::BindGroup {
+        &self.buffers.list_bind_group
+    }
+
+    /// count and indices of the pixels which need more samples
+    pub fn pixel_list(&self) -> &wgpu::Buffer {
+        &self.buffers.pixel_list
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.uniforms.width = width;
+        self.uniforms.height = height;
+        self.buffers = AdaptiveBuffers::new(
+            device,
+            &self.layout,
+            &self.list_layout,
+            &self.uniform_buffer,
+            width,
+            height,
+        );
+    }
+
+    pub fn set_threshold(&mut self, threshold: f32, min_samples: u32) {
+        self.uniforms.threshold = threshold;
+        self.uniforms.min_samples = min_samples;
+    }
+
+    /// pixels get at least this many samples before they are checked
+    pub fn min_samples(&self) -> u32 {
+        self.uniforms.min_samples
+    }
+
+    pub fn write_uniforms(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniforms]),
+        );
+    }
+
+    /// records the passes which build the pixel list from the latest accumulation,
+    /// `frame_bind_group` has to be the one used by the following compute pass
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, frame_bind_group: &wgpu::BindGroup) {
+        let (width, height) = (self.uniforms.width, self.uniforms.height);
+        let rows = DispatchArgs::for_threads([height, 1, 1], [ROW_GROUP_SIZE, 1, 1]);
+        let pixels =
+            DispatchArgs::for_threads([width, height, 1], [PIXEL_GROUP_SIZE, PIXEL_GROUP_SIZE, 1]);
+
+        let mut c_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("adaptive"),
+        });
+        c_pass.set_bind_group(0, frame_bind_group, &[]);
+        c_pass.set_bind_group(1, &self.buffers.bind_group, &[]);
+
+        c_pass.set_pipeline(&self.mark_pipeline);
+        c_pass.dispatch(pixels.x, pixels.y, pixels.z);
+        c_pass.set_pipeline(&self.count_pipeline);
+        c_pass.dispatch(rows.x, rows.y, rows.z);
+        c_pass.set_pipeline(&self.scan_pipeline);
+        c_pass.dispatch(1, 1, 1);
+        c_pass.set_pipeline(&self.compact_pipeline);
+        c_pass.dispatch(rows.x, rows.y, rows.z);
+    }
+
+    /// arguments for `dispatch_indirect`, one thread per pixel in the list
+    pub fn dispatch_args(&self) -> &wgpu::Buffer {
+        &self.buffers.dispatch_args
+    }
+
+    /// number of pixels in the list built by the last `encode`
+    pub fn remaining(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> u32 {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pixel count read back buffer"),
+            size: 4,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("pixel count read back encoder"),
+        });
+        encoder.copy_buffer_to_buffer(&self.buffers.pixel_list, 0, &buffer, 0, 4);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).expect("failed to map the read back buffer");
+        let count = bytemuck::cast_slice::<u8, u32>(&slice.get_mapped_range())[0];
+        buffer.unmap();
+        count
+    }
+}
+
+impl AdaptiveBuffers {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        list_layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let pixels = (width * height) as u64;
+        let buffer = |label, size, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsage::STORAGE | usage,
+                mapped_at_creation: false,
+            })
+        };
+        let mask = buffer("adaptive mask", 4 * pixels, wgpu::BufferUsage::empty());
+        let row_offsets = buffer(
+            "adaptive row offsets",
+            4 * height as u64,
+            wgpu::BufferUsage::empty(),
+        );
+        // the count is followed by the pixel indices
+        let pixel_list = buffer(
+            "adaptive pixel list",
+            4 + 4 * pixels,
+            wgpu::BufferUsage::COPY_SRC,
+        );
+        let dispatch_args =
+            indirect::create_buffer(device, "adaptive dispatch args", &[DispatchArgs::default()]);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("adaptive bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: mask.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: row_offsets.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: pixel_list.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: dispatch_args.as_entire_binding(),
+                },
+            ],
+        });
+        let list_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("pixel list bind group"),
+            layout: list_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: pixel_list.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            _mask: mask,
+            _row_offsets: row_offsets,
+            pixel_list,
+            dispatch_args,
+            bind_group,
+            list_bind_group,
+        }
+    }
+}
@@ -0,0 +1,158 @@
+// Builds the list of pixels which need more samples.
+// There are no atomics, so the list is compacted with a prefix sum over the rows.
+
+// frame buffer textures, see compute.wgsl
+[[group(0), binding(0)]]
+var framebuffer_src: [[access(read)]] texture_storage_2d<rgba32float>;
+
+[[group(0), binding(1)]]
+var framebuffer_dst: [[access(write)]] texture_storage_2d<rgba32float>;
+
+[[group(0), binding(2)]]
+var albedo_src: [[access(read)]] texture_storage_2d<rgba32float>;
+
+[[group(0), binding(3)]]
+var albedo_dst: [[access(write)]] texture_storage_2d<rgba32float>;
+
+[[group(0), binding(4)]]
+var normal_src: [[access(read)]] texture_storage_2d<rgba32float>;
+
+[[group(0), binding(5)]]
+var normal_dst: [[access(write)]] texture_storage_2d<rgba32float>;
+
+[[group(0), binding(6)]]
+var position_src: [[access(read)]] texture_storage_2d<rgba32float>;
+
+[[group(0), binding(7)]]
+var position_dst: [[access(write)]] texture_storage_2d<rgba32float>;
+
+// sum of the squared sample luminance
+[[group(0), binding(9)]]
+var moments_src: [[access(read)]] texture_storage_2d<r32float>;
+
+[[group(0), binding(10)]]
+var moments_dst: [[access(write)]] texture_storage_2d<r32float>;
+
+[[block]]
+struct Params {
+    // relative standard error of the mean luminance at which a pixel is converged
+    threshold: f32;
+    // samples every pixel gets before it can be converged
+    min_samples: u32;
+    width: u32;
+    height: u32;
+};
+
+[[group(1), binding(0)]]
+var<uniform> params: Params;
+
+[[block]]
+struct Mask {
+    data: [[stride(4)]] array<u32>;
+};
+
+// 1 for pixels which need more samples
+[[group(1), binding(1)]]
+var<storage> mask: [[access(read_write)]] Mask;
+
+// first list index of every row
+[[group(1), binding(2)]]
+var<storage> row_offsets: [[access(read_write)]] Mask;
+
+[[block]]
+struct PixelList {
+    count: u32;
+    data: [[stride(4)]] array<u32>;
+};
+
+// indices (y * width + x) of the pixels which need more samples
+[[group(1), binding(3)]]
+var<storage> pixel_list: [[access(read_write)]] PixelList;
+
+[[block]]
+struct DispatchArgs {
+    x: u32;
+    y: u32;
+    z: u32;
+};
+
+// arguments of the indirect dispatch of the compute pass, see indirect::DispatchArgs
+[[group(1), binding(4)]]
+var<storage> dispatch_args: [[access(read_write)]] DispatchArgs;
+
+// COMPUTE_GROUP_SIZE, the threads per workgroup of the compute pass, and the workgroup sizes
+// PIXEL_GROUP_SIZE and ROW_GROUP_SIZE are defined by adaptive.rs
+
+// marks the unconverged pixels, converged pixels are copied to the textures
+// written by the next compute pass as they are not sampled anymore
+[[stage(compute), workgroup_size(PIXEL_GROUP_SIZE, PIXEL_GROUP_SIZE)]]
+fn mark([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    if (gid.x >= params.width || gid.y >= params.height) {
+        return;
+    }
+    let pix = vec2<i32>(gid.xy);
+    let color = textureLoad(framebuffer_src, pix);
+    let moments = textureLoad(moments_src, pix);
+
+    let n = max(color.a, 1.0);
+    let mean = dot(color.rgb / n, vec3<f32>(0.2126, 0.7152, 0.0722));
+    let variance = max(moments.x / n - mean * mean, 0.0);
+    // the offset keeps dark pixels from sampling forever
+    let error = sqrt(variance / n) / (mean + 0.01);
+
+    let index = gid.y * params.width + gid.x;
+    if (u32(n) < params.min_samples || error > params.threshold) {
+        mask.data[index] = 1u;
+        return;
+    }
+    mask.data[index] = 0u;
+    textureStore(framebuffer_dst, pix, color);
+    textureStore(albedo_dst, pix, textureLoad(albedo_src, pix));
+    textureStore(normal_dst, pix, textureLoad(normal_src, pix));
+    textureStore(position_dst, pix, textureLoad(position_src, pix));
+    textureStore(moments_dst, pix, moments);
+}
+
+[[stage(compute), workgroup_size(ROW_GROUP_SIZE)]]
+fn count_rows([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    let y = gid.x;
+    if (y >= params.height) {
+        return;
+    }
+    var count: u32 = 0u;
+    for (var x: u32 = 0u; x < params.width; x = x + 1u) {
+        count = count + mask.data[y * params.width + x];
+    }
+    row_offsets.data[y] = count;
+}
+
+// exclusive prefix sum of the row counts, runs on a single thread
+[[stage(compute), workgroup_size(1)]]
+fn scan() {
+    var total: u32 = 0u;
+    for (var y: u32 = 0u; y < params.height; y = y + 1u) {
+        let count = row_offsets.data[y];
+        row_offsets.data[y] = total;
+        total = total + count;
+    }
+    pixel_list.count = total;
+    dispatch_args.x = (total + COMPUTE_GROUP_SIZE - 1u) / COMPUTE_GROUP_SIZE;
+    dispatch_args.y = 1u;
+    dispatch_args.z = 1u;
+}
+
+[[stage(compute), workgroup_size(ROW_GROUP_SIZE)]]
+fn compact([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    let y = gid.x;
+    if (y >= params.height) {
+        return;
+    }
+    var offset: u32 = row_offsets.data[y];
+    for (var x: u32 = 0u; x < params.width; x = x + 1u) {
+        let index = y * params.width + x;
+        if (mask.data[index] != 0u) {
+            pixel_list.data[offset] = index;
+            offset = offset + 1u;
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    camera::Camera,
+    cornell_box, offline,
+    renderer::{Renderer, RendererSettings},
+    scene::{Scene, SceneFile},
+    settings::{Architecture, IntegratorSettings, Settings},
+};
+
+/// passes rendered before the measurement, the driver may compile the pipelines lazily
+const WARM_UP_PASSES: u32 = 4;
+
+/// passes measured without --spp
+const DEFAULT_PASSES: u32 = 64;
+
+/// Speed of one architecture
+struct Measurement {
+    architecture: Architecture,
+    passes: u32,
+    elapsed: Duration,
+    samples_per_second: f64,
+    /// mean radiance of the image, both architectures should agree on it
+    mean_luminance: f32,
+}
+
+impl std::fmt::Display for Measurement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?}: {} passes in {:.2}s, {:.2} ms per pass, {:.2} Msamples/s, mean luminance {:.4}",
+            self.architecture,
+            self.passes,
+            self.elapsed.as_secs_f32(),
+            self.elapsed.as_secs_f64() * 1e3 / self.passes as f64,
+            self.samples_per_second / 1e6,
+            self.mean_luminance
+        )
+    }
+}
+
+fn measure(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RendererSettings,
+    passes: u32,
+) -> Measurement {
+    let mut renderer = Renderer::new(device, queue, scene, settings);
+    renderer.set_camera(camera);
+    for _ in 0..WARM_UP_PASSES {
+        renderer.render_pass(device, queue);
+    }
+    device.poll(wgpu::Maintain::Wait);
+
+    renderer.reset();
+    let start = Instant::now();
+    for _ in 0..passes {
+        renderer.render_pass(device, queue);
+    }
+    device.poll(wgpu::Maintain::Wait);
+    let elapsed = start.elapsed();
+
+    let colors = renderer.read_back(device, queue).mean_color();
+    let luminance: f32 = colors
+        .iter()
+        .map(|c| 0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2])
+        .sum();
+    let pixels = (settings.width * settings.height) as f64;
+    Measurement {
+        architecture: settings.integrator.architecture,
+        passes,
+        elapsed,
+        samples_per_second: pixels * passes as f64 / elapsed.as_secs_f64(),
+        mean_luminance: luminance / colors.len() as f32,
+    }
+}
+
+/// Renders the same passes with the megakernel and the wavefront architecture and prints
+/// their speed, the resolution and the number of passes are the ones of the offline render
+pub async fn run(settings: &Settings) -> Result<(), String> {
+    let (device, queue) = offline::request_device(Architecture::Wavefront).await?;
+    let scene = SceneFile::load_or_default(settings.scene.as_deref())?;
+    let camera = scene.camera.unwrap_or_else(cornell_box::camera);
+    let passes = settings.job.spp.unwrap_or(DEFAULT_PASSES);
+    let (width, height) = (settings.offline.width, settings.offline.height);
+    println!(
+        "benchmark: {}x{} pixels, {} passes, max depth {}",
+        width, height, passes, settings.integrator.max_depth
+    );
+
+    let mut measurements = Vec::new();
+    for &architecture in [Architecture::Megakernel, Architecture::Wavefront].iter() {
+        let renderer_settings = RendererSettings {
+            width,
+            height,
+            integrator: IntegratorSettings {
+                architecture,
+                // every pass samples all pixels, so the rates can be compared
+                adaptive_threshold: 0.,
+                ..settings.integrator
+            },
+        };
+        let measurement = measure(
+            &device,
+            &queue,
+            &scene.scene,
+            &camera,
+            &renderer_settings,
+            passes,
+        );
+        println!("{}", measurement);
+        measurements.push(measurement);
+    }
+    println!(
+        "wavefront speedup: {:.2}x",
+        measurements[1].samples_per_second / measurements[0].samples_per_second
+    );
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use once_cell::sync::Lazy;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// width and height of the blue noise mask, passed to the shaders as BLUE_NOISE_SIZE by
+/// `renderer::path_tracer_defines`
+pub const SIZE: usize = 64;
+
+/// the mask of SIZE is generated once, it is the same for every renderer
+static MASK: Lazy<Vec<f32>> = Lazy::new(|| generate(SIZE, 0));
+
+const SIGMA: f32 = 1.5;
+const KERNEL_RADIUS: isize = 6;
+
+/// Energy of a binary pattern on a torus, used to find clusters and voids
+#[derive(Clone)]
+struct Energy {
+    size: usize,
+    kernel: Vec<f32>,
+    values: Vec<f32>,
+}
+
+impl Energy {
+    fn new(size: usize) -> Self {
+        let width = (2 * KERNEL_RADIUS + 1) as usize;
+        let mut kernel = Vec::with_capacity(width * width);
+        for y in -KERNEL_RADIUS..=KERNEL_RADIUS {
+            for x in -KERNEL_RADIUS..=KERNEL_RADIUS {
+                let d2 = (x * x + y * y) as f32;
+                kernel.push((-d2 / (2. * SIGMA * SIGMA)).exp());
+            }
+        }
+        Self {
+            size,
+            kernel,
+            values: vec![0.; size * size],
+        }
+    }
+
+    fn splat(&mut self, index: usize, sign: f32) {
+        let size = self.size as isize;
+        let (px, py) = ((index % self.size) as isize, (index / self.size) as isize);
+        let width = 2 * KERNEL_RADIUS + 1;
+        for y in -KERNEL_RADIUS..=KERNEL_RADIUS {
+            for x in -KERNEL_RADIUS..=KERNEL_RADIUS {
+                let tx = (px + x).rem_euclid(size);
+                let ty = (py + y).rem_euclid(size);
+                let k = self.kernel[((y + KERNEL_RADIUS) * width + x + KERNEL_RADIUS) as usize];
+                self.values[(ty * size + tx) as usize] += sign * k;
+            }
+        }
+    }
+
+    /// index of the set pixel with the highest energy
+    fn tightest_cluster(&self, pattern: &[bool]) -> usize {
+        self.find(pattern, true, |a, b| a > b)
+    }
+
+    /// index of the unset pixel with the lowest energy
+    fn largest_void(&self, pattern: &[bool]) -> usize {
+        self.find(pattern, false, |a, b| a < b)
+    }
+
+    fn find<F: Fn(f32, f32) -> bool>(&self, pattern: &[bool], set: bool, better: F) -> usize {
+        let mut best: Option<usize> = None;
+        for (i, &value) in self.values.iter().enumerate() {
+            if pattern[i] == set && best.map_or(true, |b| better(value, self.values[b])) {
+                best = Some(i);
+            }
+        }
+        best.expect("pattern is empty or full")
+    }
+}
+
+/// blue noise mask of SIZE x SIZE used by the shaders
+pub fn mask() -> &'static [f32] {
+    &MASK
+}
+
+/// Creates a tileable blue noise mask with values in [0,1) using the
+/// void-and-cluster method (Ulichney 1993).
+pub fn generate(size: usize, seed: u64) -> Vec<f32> {
+    let n = size * size;
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // initial binary pattern with randomly placed points
+    let mut pattern = vec![false; n];
+    let mut energy = Energy::new(size);
+    let initial_points = n / 10;
+    let mut placed = 0;
+    while placed < initial_points {
+        let i = rng.gen_range(0..n);
+        if !pattern[i] {
+            pattern[i] = true;
+            energy.splat(i, 1.);
+            placed += 1;
+        }
+    }
+
+    // distribute the points evenly by moving the tightest cluster to the largest void
+    for _ in 0..n {
+        let cluster = energy.tightest_cluster(&pattern);
+        pattern[cluster] = false;
+        energy.splat(cluster, -1.);
+        let void = energy.largest_void(&pattern);
+        pattern[void] = true;
+        energy.splat(void, 1.);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0usize; n];
+
+    // phase 1: rank the initial points by removing the tightest clusters
+    let mut remaining = pattern.clone();
+    let mut remaining_energy = energy.clone();
+    for r in (0..initial_points).rev() {
+        let cluster = remaining_energy.tightest_cluster(&remaining);
+        remaining[cluster] = false;
+        remaining_energy.splat(cluster, -1.);
+        rank[cluster] = r;
+    }
+
+    // phase 2 and 3: fill the largest voids until the mask is full
+    for r in initial_points..n {
+        let void = energy.largest_void(&pattern);
+        pattern[void] = true;
+        energy.splat(void, 1.);
+        rank[void] = r;
+    }
+
+    rank.iter().map(|&r| (r as f32 + 0.5) / n as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_every_pixel_once() {
+        let size = 16;
+        let n = size * size;
+        let mask = generate(size, 3);
+        assert_eq!(mask.len(), n);
+        assert!(mask.iter().all(|&v| v > 0. && v < 1.));
+        let mut ranks: Vec<usize> = mask.iter().map(|&v| (v * n as f32) as usize).collect();
+        ranks.sort_unstable();
+        assert_eq!(ranks, (0..n).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn generates_the_mask_once() {
+        assert_eq!(mask().len(), SIZE * SIZE);
+        assert!(std::ptr::eq(mask(), mask()));
+    }
+
+    /// RMS error of the means of 8x8 pixel blocks when every pixel estimates the integral of a
+    /// step function with `spp` samples, the shared sequence of the sampler is rotated by `offsets`
+    fn block_error(offsets: &[f32], size: usize, spp: u32) -> f32 {
+        let f = |u: f32| if u < 0.3 { 1. } else { 0. };
+        let estimates: Vec<f32> = offsets
+            .iter()
+            .map(|&offset| {
+                let sum: f32 = (0..spp)
+                    .map(|pass| f((pass as f32 * 0.618_034 + 0.37 + offset).fract()))
+                    .sum();
+                sum / spp as f32
+            })
+            .collect();
+        let blocks = size / 8;
+        let mut squared = 0.;
+        for by in 0..blocks {
+            for bx in 0..blocks {
+                let mut mean = 0.;
+                for y in 8 * by..8 * by + 8 {
+                    for x in 8 * bx..8 * bx + 8 {
+                        mean += estimates[y * size + x] / 64.;
+                    }
+                }
+                squared += (mean - 0.3f32).powi(2);
+            }
+        }
+        (squared / (blocks * blocks) as f32).sqrt()
+    }
+
+    #[test]
+    fn converges_faster_than_white_noise() {
+        // blue noise moves the error to high frequencies, neighbouring pixels cancel it out
+        let blue = mask();
+        let mut rng = StdRng::seed_from_u64(0);
+        let white: Vec<f32> = (0..SIZE * SIZE).map(|_| rng.gen_range(0.0..1.0)).collect();
+        for &spp in [1, 4].iter() {
+            let (blue_error, white_error) =
+                (block_error(blue, SIZE, spp), block_error(&white, SIZE, spp));
+            assert!(
+                blue_error < 0.5 * white_error,
+                "{} spp: blue noise error {} white noise error {}",
+                spp,
+                blue_error,
+                white_error
+            );
+        }
+    }
+}
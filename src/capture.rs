@@ -0,0 +1,168 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use cgmath::Deg;
+
+use crate::{camera::Camera, frame_buffer::FrameBufferData, output, settings::Settings};
+
+/// UTC date and time as YYYYMMDD-HHMMSS
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = ((seconds / 86400) as i64, seconds % 86400);
+    // civil from days (Howard Hinnant), the era is 400 years
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// JSON number of `value`, JSON has no NaN or infinity so they are written as null
+fn number(value: f32) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// camera pose and settings of a capture as JSON
+fn sidecar(timestamp: &str, spp: u32, camera: &Camera, settings: &Settings) -> String {
+    let [yaw, pitch, roll] = camera.angles();
+    let deg = |a| number(Deg::from(a).0);
+    let p = camera.position;
+    let integrator = &settings.integrator;
+    let display = &settings.display;
+    let denoise = &settings.denoise;
+    format!(
+        r#"{{
+  "timestamp": "{}",
+  "spp": {},
+  "camera": {{
+    "position": [{}, {}, {}],
+    "yaw": {},
+    "pitch": {},
+    "roll": {}
+  }},
+  "integrator": {{
+    "max_depth": {},
+    "min_distance": {},
+    "rr_depth": {},
+    "radiance_clamp": {},
+    "debug_view": "{:?}",
+    "sampler": "{:?}",
+    "filter": "{:?}",
+    "filter_width": {},
+    "adaptive_threshold": {},
+    "adaptive_min_samples": {}
+  }},
+  "display": {{
+    "tone_mapper": "{:?}",
+    "exposure": {},
+    "white_point": {},
+    "white_balance": {}
+  }},
+  "denoise": {{
+    "mode": "{:?}",
+    "iterations": {}
+  }}
+}}
+"#,
+        timestamp,
+        spp,
+        number(p.x),
+        number(p.y),
+        number(p.z),
+        deg(yaw),
+        deg(pitch),
+        deg(roll),
+        integrator.max_depth,
+        number(integrator.min_distance),
+        integrator.rr_depth,
+        number(integrator.radiance_clamp),
+        integrator.debug_view,
+        integrator.sampler,
+        integrator.filter,
+        number(integrator.filter_width),
+        number(integrator.adaptive_threshold),
+        integrator.adaptive_min_samples,
+        display.tone_mapper,
+        number(display.exposure),
+        number(display.white_point),
+        number(display.white_balance),
+        denoise.mode,
+        denoise.iterations,
+    )
+}
+
+/// Saves the accumulation as tone mapped PNG and linear EXR to `dir`,
+/// with a JSON file holding the camera pose and the settings.
+/// Returns the path of the PNG
+pub fn save(
+    dir: &Path,
+    data: &FrameBufferData,
+    spp: u32,
+    camera: &Camera,
+    settings: &Settings,
+) -> Result<PathBuf, String> {
+    let timestamp = timestamp(SystemTime::now());
+    let base = dir.join(format!("rey_{}_{}spp", timestamp, spp));
+
+    let png = base.with_extension("png");
+    output::save(data, &png, &settings.display, false)?;
+    output::save(data, &base.with_extension("exr"), &settings.display, false)?;
+
+    let json = base.with_extension("json");
+    fs::write(&json, sidecar(&timestamp, spp, camera, settings))
+        .map_err(|e| format!("failed to save '{}': {}", json.display(), e))?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(seconds: u64) -> String {
+        timestamp(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn converts_civil_dates() {
+        assert_eq!(at(0), "19700101-000000");
+        assert_eq!(at(951_868_799), "20000229-235959");
+        assert_eq!(at(951_868_800), "20000301-000000");
+        assert_eq!(at(1_709_210_096), "20240229-123456");
+    }
+
+    #[test]
+    fn writes_valid_json_numbers() {
+        let mut settings = Settings::default();
+        settings.integrator.radiance_clamp = f32::INFINITY;
+        settings.display.exposure = f32::NAN;
+        let camera = Camera::new((1., 2.5, -3.), Deg(90.), Deg(0.), Deg(0.));
+        let json = sidecar("20000301-000000", 16, &camera, &settings);
+        assert!(json.contains(r#""position": [1, 2.5, -3]"#));
+        assert!(json.contains(r#""yaw": 90"#));
+        assert!(json.contains(r#""min_distance": 0.001"#));
+        assert!(json.contains(r#""radiance_clamp": null"#));
+        assert!(json.contains(r#""exposure": null"#));
+        assert!(!json.contains("inf") && !json.contains("NaN"), "{}", json);
+    }
+}
@@ -0,0 +1,220 @@
+use std::{fs, path::Path};
+
+use crate::frame_buffer::FrameBufferData;
+
+/// identifies checkpoint files, the last byte is the version of the format
+const MAGIC: &[u8; 8] = b"REYCKPT2";
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// FNV-1a hash of the concatenated parts, unlike the std hashers it is stable between builds
+pub fn hash(parts: &[&[u8]]) -> u64 {
+    let mut hash = FNV_OFFSET;
+    for &b in parts.iter().flat_map(|p| p.iter()) {
+        hash = (hash ^ b as u64).wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// State of an interrupted render.
+/// The random numbers only depend on the pixel and the pass,
+/// so the pass is all that is needed to continue the sample sequences
+pub struct Checkpoint {
+    /// see `Renderer::scene_hash`
+    pub scene_hash: u64,
+    pub pass: u32,
+    pub data: FrameBufferData,
+}
+
+/// Reads the little endian values of a checkpoint file
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < n {
+            return Err("the checkpoint is truncated".to_string());
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let low = self.u32()? as u64;
+        let high = self.u32()? as u64;
+        Ok(low | high << 32)
+    }
+}
+
+impl Checkpoint {
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let data = &self.data;
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&self.scene_hash.to_le_bytes());
+        for v in [data.width, data.height, self.pass, data.aovs.len() as u32].iter() {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        let floats = data.color.iter().chain(data.aovs.iter().flatten());
+        for v in floats.flatten().chain(data.moments.iter()) {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in data.ids.iter().flatten() {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        // a crash while writing must not destroy the previous checkpoint
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, out)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| format!("failed to save the checkpoint '{}': {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path)
+            .map_err(|e| format!("failed to read the checkpoint '{}': {}", path.display(), e))?;
+        let mut reader = Reader { bytes: &bytes };
+        if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(format!("'{}' is not a rey checkpoint", path.display()));
+        }
+        let scene_hash = reader.u64()?;
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let pass = reader.u32()?;
+        let num_aovs = reader.u32()?;
+
+        let pixels = width as usize * height as usize;
+        let color = FrameBufferData::parse_f32(reader.take(pixels * 16)?);
+        let aovs = (0..num_aovs)
+            .map(|_| Ok(FrameBufferData::parse_f32(reader.take(pixels * 16)?)))
+            .collect::<Result<_, String>>()?;
+        let moments = FrameBufferData::parse_r32(reader.take(pixels * 4)?);
+        let ids = FrameBufferData::parse_u32(reader.take(pixels * 8)?);
+        if !reader.bytes.is_empty() {
+            return Err("the checkpoint has trailing data".to_string());
+        }
+        Ok(Checkpoint {
+            scene_hash,
+            pass,
+            data: FrameBufferData {
+                width,
+                height,
+                color,
+                aovs,
+                ids,
+                moments,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// file in the temp directory which is removed when the test ends
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let name = format!("rey-{}-{}.ckpt", std::process::id(), name);
+            TempFile(std::env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            fs::remove_file(&self.0).ok();
+        }
+    }
+
+    fn checkpoint() -> Checkpoint {
+        Checkpoint {
+            scene_hash: 0x0123_4567_89ab_cdef,
+            pass: 42,
+            data: FrameBufferData {
+                width: 3,
+                height: 2,
+                color: (0..6).map(|i| [i as f32, 0.5, -1., 7.]).collect(),
+                aovs: vec![
+                    vec![[1., 2., 3., 4.]; 6],
+                    (0..6).map(|i| [0., 0., 1., i as f32]).collect(),
+                ],
+                ids: (0..6).map(|i| [i, u32::MAX - i]).collect(),
+                moments: (0..6).map(|i| i as f32 * 0.25).collect(),
+            },
+        }
+    }
+
+    /// bytes of a saved checkpoint
+    fn saved_bytes(file: &TempFile) -> Vec<u8> {
+        checkpoint().save(&file.0).unwrap();
+        fs::read(&file.0).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let file = TempFile::new("round_trip");
+        let saved = checkpoint();
+        saved.save(&file.0).unwrap();
+        let loaded = Checkpoint::load(&file.0).unwrap();
+        assert_eq!(loaded.scene_hash, saved.scene_hash);
+        assert_eq!(loaded.pass, saved.pass);
+        assert_eq!(
+            (loaded.data.width, loaded.data.height),
+            (saved.data.width, saved.data.height)
+        );
+        assert_eq!(loaded.data.color, saved.data.color);
+        assert_eq!(loaded.data.aovs, saved.data.aovs);
+        assert_eq!(loaded.data.ids, saved.data.ids);
+        assert_eq!(loaded.data.moments, saved.data.moments);
+    }
+
+    #[test]
+    fn reports_truncated_files() {
+        let file = TempFile::new("truncated");
+        let bytes = saved_bytes(&file);
+        fs::write(&file.0, &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(
+            Checkpoint::load(&file.0).err().unwrap(),
+            "the checkpoint is truncated"
+        );
+    }
+
+    #[test]
+    fn reports_trailing_data() {
+        let file = TempFile::new("trailing");
+        let mut bytes = saved_bytes(&file);
+        bytes.push(0);
+        fs::write(&file.0, &bytes).unwrap();
+        assert_eq!(
+            Checkpoint::load(&file.0).err().unwrap(),
+            "the checkpoint has trailing data"
+        );
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let file = TempFile::new("magic");
+        let mut bytes = saved_bytes(&file);
+        bytes[..MAGIC.len()].copy_from_slice(b"REYCKPT0");
+        fs::write(&file.0, &bytes).unwrap();
+        let error = Checkpoint::load(&file.0).err().unwrap();
+        assert!(error.ends_with("is not a rey checkpoint"), "{}", error);
+    }
+
+    #[test]
+    fn hash_is_fnv_1a() {
+        assert_eq!(hash(&[]), FNV_OFFSET);
+        assert_eq!(hash(&[b"a"]), 0xaf63_dc4c_8601_ec8c);
+        // the parts are concatenated
+        assert_eq!(hash(&[b"foo", b"bar"]), 0x8594_4171_f739_67e8);
+    }
+}
@@ -0,0 +1,191 @@
+// Bindings, scene data and constants shared by the path tracer kernels
+
+let PI:f32 = 3.14159265359;
+
+[[group(0), binding(0)]]
+var framebuffer_src: [[access(read)]] texture_storage_2d<rgba32float>;
+
+[[group(0), binding(1)]]
+var framebuffer_dst: [[access(write)]] texture_storage_2d<rgba32float>;
+
+// accumulated AOVs of the first hit, the order has to match AOV_NAMES in frame_buffer.rs
+[[group(0), binding(2)]]
+var albedo_src: [[access(read)]] texture_storage_2d<rgba32float>;
+
+[[group(0), binding(3)]]
+var albedo_dst: [[access(write)]] texture_storage_2d<rgba32float>;
+
+[[group(0), binding(4)]]
+var normal_src: [[access(read)]] texture_storage_2d<rgba32float>;
+
+[[group(0), binding(5)]]
+var normal_dst: [[access(write)]] texture_storage_2d<rgba32float>;
+
+// xyz: world position, w: linear depth along the view direction
+[[group(0), binding(6)]]
+var position_src: [[access(read)]] texture_storage_2d<rgba32float>;
+
+[[group(0), binding(7)]]
+var position_dst: [[access(write)]] texture_storage_2d<rgba32float>;
+
+// r: material id, g: object id of the first hit, 0 if nothing was hit
+[[group(0), binding(8)]]
+var ids: [[access(write)]] texture_storage_2d<rg32uint>;
+
+// sum of the squared sample luminance, used to estimate the variance
+[[group(0), binding(9)]]
+var moments_src: [[access(read)]] texture_storage_2d<r32float>;
+
+[[group(0), binding(10)]]
+var moments_dst: [[access(write)]] texture_storage_2d<r32float>;
+
+[[block]]
+struct Uniforms {
+    u_view_proj: mat4x4<f32>;
+    time: f32;
+    pass: u32;
+	num_faces: u32; // TODO remove when array
+    max_depth: u32;
+    min_distance: f32;
+    // bounce at which russian roulette starts, 0 disables it
+    rr_depth: u32;
+    // maximum radiance of a single sample, 0 disables clamping
+    radiance_clamp: f32;
+    debug_view: u32;
+    sampler: u32;
+    pixel_filter: u32;
+    filter_width: f32;
+    // only the pixels in pixel_list are sampled
+    adaptive: u32;
+    // position of the frame buffer in the image when rendering tiles
+    tile_offset: vec2<u32>;
+    image_size: vec2<u32>;
+    // region of the frame buffer covered by the dispatch, the other pixels are frozen
+    roi_offset: vec2<u32>;
+    roi_size: vec2<u32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> uniforms: Uniforms;
+
+[[block]]
+struct BlueNoise {
+    data: [[stride(4)]] array<f32>;
+};
+
+[[group(1), binding(1)]]
+var<storage> blue_noise: [[access(read)]] BlueNoise;
+
+struct Vertex{
+	x:f32;
+	y:f32;
+	z:f32;
+};
+
+[[block]]
+struct Vertices{
+	data:[[stride(12)]] array<Vertex>;
+};
+
+[[group(2), binding(0)]]
+var<storage> vertices: [[access(read)]] Vertices;
+
+
+// xyz: vertex indices, w: material index
+[[block]]
+struct Faces {
+    data: [[stride(16)]] array<vec4<u32>>;
+};
+
+[[group(2), binding(1)]]
+var<storage> faces: [[access(read)]] Faces;
+
+struct Material {
+    // rgb: albedo, a: strength of the emission
+    color: vec4<f32>;
+};
+
+// MAX_MATERIALS is defined by renderer.rs
+[[block]]
+struct SceneData {
+    // xyz: center, w: radius of the sphere light
+    light: vec4<f32>;
+    // the material of the light follows the ones of the faces
+    light_material: u32;
+    materials: [[stride(16)]] array<Material, MAX_MATERIALS>;
+};
+
+[[group(2), binding(2)]]
+var<uniform> scene: SceneData;
+
+[[block]]
+struct PixelList {
+    count: u32;
+    data: [[stride(4)]] array<u32>;
+};
+
+// pixels which need more samples, written by adaptive.wgsl
+[[group(3), binding(0)]]
+var<storage> pixel_list: [[access(read)]] PixelList;
+
+struct Ray {
+    orig: vec3<f32>;
+    dir: vec3<f32>;
+};
+
+struct Intersection {
+    pos: vec3<f32>;
+    normal: vec3<f32>;
+    materialIdx: u32;
+    // spheres first, followed by the faces
+    objectIdx: u32;
+    // barycentric coordinates of p2 and p3, zero for spheres
+    uv: vec2<f32>;
+    lambda: f32;
+};
+
+struct Camera {
+    focal_length: f32;
+    ratio: f32;
+};
+
+struct Sphere {
+    radius: f32;
+    center: vec3<f32>;
+    materialIdx: u32;
+};
+
+struct Triangle {
+	p1: vec3<f32>;
+	p2: vec3<f32>;
+	p3: vec3<f32>;
+    materialIdx: u32;
+};
+
+// the sphere light is the only sphere, the object ids of the faces follow it
+let num_spheres:u32 = 1u;
+
+fn light_sphere() -> Sphere {
+    return Sphere(scene.light.w, scene.light.xyz, scene.light_material);
+}
+
+// debug views selectable with uniforms.debug_view, DEBUG_OFF renders the image
+let DEBUG_OFF: u32 = 0u;
+let DEBUG_SHADING_NORMAL: u32 = 1u;
+let DEBUG_GEOMETRIC_NORMAL: u32 = 2u;
+let DEBUG_ALBEDO: u32 = 3u;
+let DEBUG_DEPTH: u32 = 4u;
+let DEBUG_BARYCENTRICS: u32 = 5u;
+let DEBUG_TRIANGLE_ID: u32 = 6u;
+let DEBUG_TRAVERSAL_STEPS: u32 = 7u;
+let DEBUG_SAMPLE_COUNT: u32 = 8u;
+
+// depth mapped to 50% gray in the depth view
+let DEBUG_DEPTH_SCALE: f32 = 500.0;
+// DEBUG_MAX_SAMPLES, the sample count mapped to the top of the heat map, is defined by
+// renderer.rs
+
+// samplers selectable with uniforms.sampler
+let SAMPLER_RANDOM: u32 = 0u;
+let SAMPLER_SOBOL: u32 = 1u;
+let SAMPLER_BLUE_NOISE: u32 = 2u;
@@ -1,4 +1,4 @@
-use crate::lib::Vertex;
+use crate::{camera::Camera, scene::Vertex};
 
 pub const VERTICES: [Vertex; 72] = [
     // Floor
@@ -271,3 +271,13 @@ pub const FACES: [[u32; 3]; 36] = [
     [68, 69, 70],
     [70, 71, 68],
 ];
+
+/// camera looking into the box
+pub fn camera() -> Camera {
+    Camera::new(
+        (250.0, 350.0, -260.0),
+        cgmath::Deg(0.0),
+        cgmath::Deg(0.0),
+        cgmath::Deg(0.0),
+    )
+}
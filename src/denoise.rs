@@ -0,0 +1,634 @@
+use cgmath::{Matrix4, SquareMatrix};
+
+use crate::{
+    frame_buffer::{self, FrameBuffer, FrameBufferData},
+    indirect::DispatchArgs,
+    pipeline,
+    settings::{DenoiseMode, DenoiseSettings},
+    shader::{Defines, ShaderLibrary},
+};
+
+/// maximum number of à-trous iterations, limits the size of the uniform buffer
+pub const MAX_ITERATIONS: u32 = 8;
+
+/// frames with less samples estimate the variance spatially, see denoise.wgsl
+const MIN_VARIANCE_SAMPLES: f32 = 4.;
+
+/// dynamic offsets of uniform buffers have to be aligned to 256 bytes
+const UNIFORM_STRIDE: u64 = 256;
+
+const WORKGROUP_SIZE: u32 = 16;
+
+/// constants of denoise.wgsl
+pub(crate) fn defines() -> Defines {
+    Defines::new().set_u32("WORKGROUP_SIZE", WORKGROUP_SIZE)
+}
+
+/// Uniforms of denoise.wgsl, one per dispatch
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DenoiseUniforms {
+    prev_world_to_camera: [[f32; 4]; 4],
+    mode: u32,
+    step: u32,
+    last: u32,
+    moved: u32,
+    sigma_color: f32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+    alpha_min: f32,
+}
+
+/// Denoises the frame buffer between the compute and the display pass.
+/// The result is written to its own texture which is displayed instead of the frame buffer
+pub struct Denoiser {
+    prepare_pipeline: wgpu::ComputePipeline,
+    atrous_pipeline: wgpu::ComputePipeline,
+
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+
+    frame_layout: wgpu::BindGroupLayout,
+    io_layout: wgpu::BindGroupLayout,
+    textures: DenoiseTextures,
+
+    /// camera of the previous frame, used to reproject the history
+    prev_camera: Matrix4<f32>,
+    /// index of the history textures written by the last frame
+    history: usize,
+}
+
+/// size dependent resources of the denoiser
+struct DenoiseTextures {
+    textures: Vec<wgpu::Texture>,
+    /// frame_bind_groups[i] reads the frame buffer textures with index i
+    frame_bind_groups: [wgpu::BindGroup; 2],
+    /// io_bind_groups[2 * s + h] reads temporary texture s and history h and writes the other ones
+    io_bind_groups: Vec<wgpu::BindGroup>,
+    render_bind_group: wgpu::BindGroup,
+    width: u32,
+    height: u32,
+}
+
+fn sampled_texture(
+    binding: u32,
+    sample_type: wgpu::TextureSampleType,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStage::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+fn texture_entry(binding: u32, view: &wgpu::TextureView) -> wgpu::BindGroupEntry {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: wgpu::BindingResource::TextureView(view),
+    }
+}
+
+fn storage_texture(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStage::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: frame_buffer::FRAME_BUFFER_FORMAT,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        },
+        count: None,
+    }
+}
+
+impl Denoiser {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frame_buffer: &FrameBuffer,
+        render_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let float = wgpu::TextureSampleType::Float { filterable: false };
+        let frame_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("denoise frame layout"),
+            entries: &[
+                sampled_texture(0, float),
+                sampled_texture(1, float),
+                sampled_texture(2, float),
+                sampled_texture(3, float),
+                sampled_texture(4, wgpu::TextureSampleType::Uint),
+                sampled_texture(5, float),
+            ],
+        });
+        let io_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("denoise io layout"),
+            entries: &[
+                sampled_texture(0, float),
+                storage_texture(1),
+                sampled_texture(2, float),
+                storage_texture(3),
+                sampled_texture(4, float),
+                storage_texture(5),
+                storage_texture(6),
+            ],
+        });
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("denoise uniform layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(
+                        std::mem::size_of::<DenoiseUniforms>() as u64
+                    ),
+                },
+                count: None,
+            }],
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Denoise Uniform Buffer"),
+            size: UNIFORM_STRIDE * (MAX_ITERATIONS as u64 + 1),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("denoise uniform bind group"),
+            layout: &uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &uniform_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<DenoiseUniforms>() as u64),
+                }),
+            }],
+        });
+
+        let library = ShaderLibrary::builtin();
+        let shader = || {
+            library
+                .module("denoise_shader", "denoise.wgsl", &defines())
+                .expect("invalid denoise shader")
+        };
+        let layouts = [&frame_layout, &uniform_layout, &io_layout];
+        let prepare_pipeline = pipeline::create_compute_pipeline_with_entry(
+            device,
+            &layouts,
+            shader(),
+            "prepare",
+            Some("DenoisePreparePipeline"),
+        );
+        let atrous_pipeline = pipeline::create_compute_pipeline_with_entry(
+            device,
+            &layouts,
+            shader(),
+            "atrous",
+            Some("DenoiseAtrousPipeline"),
+        );
+
+        let textures = DenoiseTextures::new(
+            device,
+            queue,
+            frame_buffer,
+            &frame_layout,
+            &io_layout,
+            render_layout,
+        );
+
+        Self {
+            prepare_pipeline,
+            atrous_pipeline,
+
+            uniform_buffer,
+            uniform_bind_group,
+
+            frame_layout,
+            io_layout,
+            textures,
+
+            prev_camera: Matrix4::identity(),
+            history: 0,
+        }
+    }
+
+    /// recreates the textures, has to be called whenever the frame buffer was recreated
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frame_buffer: &FrameBuffer,
+        render_layout: &wgpu::BindGroupLayout,
+    ) {
+        for texture in self.textures.textures.iter() {
+            texture.destroy();
+        }
+        self.textures = DenoiseTextures::new(
+            device,
+            queue,
+            frame_buffer,
+            &self.frame_layout,
+            &self.io_layout,
+            render_layout,
+        );
+        self.history = 0;
+    }
+
+    /// records the denoise passes for the latest accumulation of the frame buffer.
+    /// `camera` is the camera to world matrix the accumulation was rendered with
+    pub fn encode(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        frame_buffer: &FrameBuffer,
+        camera: Matrix4<f32>,
+        settings: &DenoiseSettings,
+    ) {
+        let iterations = settings.iterations.max(1).min(MAX_ITERATIONS);
+        let base = DenoiseUniforms {
+            prev_world_to_camera: self
+                .prev_camera
+                .invert()
+                .unwrap_or_else(Matrix4::identity)
+                .into(),
+            mode: settings.mode as u32,
+            step: 1,
+            last: 0,
+            moved: (camera != self.prev_camera) as u32,
+            sigma_color: settings.sigma_color,
+            sigma_normal: settings.sigma_normal,
+            sigma_depth: settings.sigma_depth,
+            alpha_min: settings.alpha_min,
+        };
+        self.prev_camera = camera;
+
+        let mut uniforms = vec![0u8; (UNIFORM_STRIDE * (iterations as u64 + 1)) as usize];
+        for i in 0..=iterations {
+            let params = DenoiseUniforms {
+                // the first entry belongs to the prepare pass
+                step: 1 << i.saturating_sub(1),
+                last: (i == iterations) as u32,
+                ..base
+            };
+            let offset = (UNIFORM_STRIDE * i as u64) as usize;
+            let bytes = bytemuck::bytes_of(&params);
+            uniforms[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, &uniforms);
+
+        let args = DispatchArgs::for_threads(
+            [self.textures.width, self.textures.height, 1],
+            [WORKGROUP_SIZE, WORKGROUP_SIZE, 1],
+        );
+        let textures = &self.textures;
+        let history = self.history;
+        let io = |temporary: usize| &textures.io_bind_groups[2 * temporary + history];
+
+        let mut c_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("denoise"),
+        });
+        c_pass.set_bind_group(0, &textures.frame_bind_groups[frame_buffer.current()], &[]);
+
+        c_pass.set_pipeline(&self.prepare_pipeline);
+        c_pass.set_bind_group(1, &self.uniform_bind_group, &[0]);
+        c_pass.set_bind_group(2, io(0), &[]);
+        c_pass.dispatch(args.x, args.y, args.z);
+
+        c_pass.set_pipeline(&self.atrous_pipeline);
+        for i in 1..=iterations {
+            let offset = (UNIFORM_STRIDE * i as u64) as wgpu::DynamicOffset;
+            c_pass.set_bind_group(1, &self.uniform_bind_group, &[offset]);
+            // the prepare pass wrote texture 1
+            c_pass.set_bind_group(2, io(i as usize % 2), &[]);
+            c_pass.dispatch(args.x, args.y, args.z);
+        }
+        drop(c_pass);
+
+        if settings.mode == DenoiseMode::Svgf {
+            self.history = 1 - self.history;
+        }
+    }
+
+    /// bind group for the display pass, reads the denoised image
+    pub fn render_bind_group(&self) -> &wgpu::BindGroup {
+        &self.textures.render_bind_group
+    }
+}
+
+impl DenoiseTextures {
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frame_buffer: &FrameBuffer,
+        frame_layout: &wgpu::BindGroupLayout,
+        io_layout: &wgpu::BindGroupLayout,
+        render_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let (width, height) = (frame_buffer.width, frame_buffer.height);
+        // temporary 0 and 1, history 0 and 1, moments 0 and 1, output
+        let textures = (0..7)
+            .map(|_| {
+                frame_buffer::create_empty_texture(
+                    device,
+                    queue,
+                    width,
+                    height,
+                    frame_buffer::FRAME_BUFFER_FORMAT,
+                    // the zeros are written to them, the history starts empty
+                    wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+                )
+            })
+            .collect::<Vec<_>>();
+        let views = textures
+            .iter()
+            .map(|t| t.create_view(&wgpu::TextureViewDescriptor::default()))
+            .collect::<Vec<_>>();
+        let view =
+            |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let frame_bind_group = |i: usize| {
+            let frame_views = [
+                view(&frame_buffer.textures[i]),
+                view(&frame_buffer.aov_textures[0][i]),
+                view(&frame_buffer.aov_textures[1][i]),
+                view(&frame_buffer.aov_textures[2][i]),
+                view(&frame_buffer.id_texture),
+                view(&frame_buffer.moment_textures[i]),
+            ];
+            let entries = frame_views
+                .iter()
+                .enumerate()
+                .map(|(binding, v)| texture_entry(binding as u32, v))
+                .collect::<Vec<_>>();
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("denoise frame bind group"),
+                layout: frame_layout,
+                entries: &entries,
+            })
+        };
+        let io_bind_group = |temporary: usize, history: usize| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("denoise io bind group"),
+                layout: io_layout,
+                entries: &[
+                    texture_entry(0, &views[temporary]),
+                    texture_entry(1, &views[1 - temporary]),
+                    texture_entry(2, &views[2 + history]),
+                    texture_entry(3, &views[3 - history]),
+                    texture_entry(4, &views[4 + history]),
+                    texture_entry(5, &views[5 - history]),
+                    texture_entry(6, &views[6]),
+                ],
+            })
+        };
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("denoise render bind group"),
+            layout: render_layout,
+            entries: &[texture_entry(0, &views[6])],
+        });
+
+        Self {
+            frame_bind_groups: [frame_bind_group(0), frame_bind_group(1)],
+            io_bind_groups: vec![
+                io_bind_group(0, 0),
+                io_bind_group(0, 1),
+                io_bind_group(1, 0),
+                io_bind_group(1, 1),
+            ],
+            render_bind_group,
+            textures,
+            width,
+            height,
+        }
+    }
+}
+
+fn luminance(rgb: [f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+fn kernel(offset: i32) -> f32 {
+    match offset.abs() {
+        0 => 0.375,
+        1 => 0.25,
+        _ => 0.0625,
+    }
+}
+
+/// cpu version of the à-trous mode of denoise.wgsl, returns the denoised mean radiance
+pub fn denoise(frame: &FrameBufferData, settings: &DenoiseSettings) -> Vec<[f32; 3]> {
+    let (width, height) = (frame.width as i32, frame.height as i32);
+    let pixels = frame.width as usize * frame.height as usize;
+    let index = |x: i32, y: i32| (y * width + x) as usize;
+    let inside = |x: i32, y: i32| x >= 0 && y >= 0 && x < width && y < height;
+
+    let color = frame.mean_color();
+    let albedo = frame.mean_aov(0);
+    let normal = frame.mean_aov(1);
+    let position = frame.mean_aov(2);
+    let demodulation = albedo
+        .iter()
+        .map(|a| {
+            let d = |c: f32| if c < 0.001 { 1. } else { c };
+            [d(a[0]), d(a[1]), d(a[2])]
+        })
+        .collect::<Vec<_>>();
+    let normals = normal
+        .iter()
+        .map(|n| {
+            let l = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            if l > 0. {
+                [n[0] / l, n[1] / l, n[2] / l]
+            } else {
+                [0.; 3]
+            }
+        })
+        .collect::<Vec<_>>();
+    let illumination = (0..pixels)
+        .map(|i| {
+            let (c, d) = (color[i], demodulation[i]);
+            [c[0] / d[0], c[1] / d[1], c[2] / d[2]]
+        })
+        .collect::<Vec<_>>();
+
+    // prepare
+    let mut variance = (0..pixels)
+        .map(|i| {
+            let samples = frame.samples(i).max(1.);
+            let mean = luminance(color[i]);
+            let d = luminance(demodulation[i]);
+            (frame.moments[i] / samples - mean * mean).max(0.) / samples / (d * d)
+        })
+        .collect::<Vec<_>>();
+    for y in 0..height {
+        for x in 0..width {
+            let i = index(x, y);
+            if frame.samples(i).max(1.) >= MIN_VARIANCE_SAMPLES {
+                continue;
+            }
+            let (mut sum, mut sum_squared, mut count) = (0., 0., 0.);
+            for (qx, qy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy))) {
+                if inside(qx, qy) && frame.ids[index(qx, qy)][1] == frame.ids[i][1] {
+                    let l = luminance(illumination[index(qx, qy)]);
+                    sum += l;
+                    sum_squared += l * l;
+                    count += 1.;
+                }
+            }
+            let mean = sum / count;
+            let mean_squared = sum_squared / count;
+            variance[i] = (mean_squared - mean * mean).max(0.);
+        }
+    }
+
+    let mut current = illumination;
+    for iteration in 0..settings.iterations.max(1).min(MAX_ITERATIONS) {
+        let spacing = 1 << iteration;
+        let mut next = vec![[0.; 3]; pixels];
+        let mut next_variance = vec![0.; pixels];
+        for y in 0..height {
+            for x in 0..width {
+                let center = index(x, y);
+                let l_p = luminance(current[center]);
+                let d_p = position[center][3];
+
+                let (mut sum, mut weights) = (0., 0.);
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        if inside(x + dx, y + dy) {
+                            let w = ((2 - dx.abs()) * (2 - dy.abs())) as f32;
+                            sum += w * variance[index(x + dx, y + dy)];
+                            weights += w;
+                        }
+                    }
+                }
+                let deviation = (sum / weights).sqrt();
+
+                let mut c = [0.; 3];
+                let (mut v, mut weights) = (0., 0.);
+                for dy in -2..=2 {
+                    for dx in -2..=2 {
+                        let (qx, qy) = (x + dx * spacing, y + dy * spacing);
+                        if !inside(qx, qy) {
+                            continue;
+                        }
+                        let q = index(qx, qy);
+                        let mut w = kernel(dx) * kernel(dy);
+                        if dx != 0 || dy != 0 {
+                            let (n_p, n_q) = (normals[center], normals[q]);
+                            let cos = n_p[0] * n_q[0] + n_p[1] * n_q[1] + n_p[2] * n_q[2];
+                            let w_normal = cos.max(0.).powf(settings.sigma_normal);
+                            let w_depth = (-(d_p - position[q][3]).abs()
+                                / (settings.sigma_depth * spacing as f32 * d_p + 1e-6))
+                                .exp();
+                            let w_color = (-(l_p - luminance(current[q])).abs()
+                                / (settings.sigma_color * deviation + 1e-6))
+                                .exp();
+                            w *= w_normal * w_depth * w_color;
+                        }
+                        for (sum, value) in c.iter_mut().zip(current[q].iter()) {
+                            *sum += w * value;
+                        }
+                        v += w * w * variance[q];
+                        weights += w;
+                    }
+                }
+                next[center] = [c[0] / weights, c[1] / weights, c[2] / weights];
+                next_variance[center] = v / (weights * weights);
+            }
+        }
+        current = next;
+        variance = next_variance;
+    }
+
+    current
+        .iter()
+        .zip(demodulation.iter())
+        .map(|(c, d)| [c[0] * d[0], c[1] * d[1], c[2] * d[2]])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// frame of a plane at depth 1 with the given normals and mean colors
+    fn frame(width: u32, height: u32, normal: &[[f32; 3]], color: &[[f32; 3]]) -> FrameBufferData {
+        let samples = 1.;
+        let pixels = (width * height) as usize;
+        FrameBufferData {
+            width,
+            height,
+            color: color
+                .iter()
+                .map(|c| [c[0] * samples, c[1] * samples, c[2] * samples, samples])
+                .collect(),
+            aovs: vec![
+                vec![[1., 1., 1., 0.]; pixels],
+                normal.iter().map(|n| [n[0], n[1], n[2], 0.]).collect(),
+                vec![[0., 0., 1., 1.]; pixels],
+            ],
+            ids: vec![[1, 1]; pixels],
+            moments: color
+                .iter()
+                .map(|c| luminance(*c).powi(2) * samples)
+                .collect(),
+        }
+    }
+
+    fn variance(values: &[[f32; 3]]) -> f32 {
+        let n = values.len() as f32;
+        let mean = values.iter().map(|v| v[0]).sum::<f32>() / n;
+        values.iter().map(|v| (v[0] - mean).powi(2)).sum::<f32>() / n
+    }
+
+    #[test]
+    fn keeps_constant_image() {
+        let frame = frame(16, 16, &[[0., 0., -1.]; 256], &[[0.5, 0.25, 1.]; 256]);
+        for c in denoise(&frame, &DenoiseSettings::default()) {
+            assert!((c[0] - 0.5).abs() < 1e-5);
+            assert!((c[1] - 0.25).abs() < 1e-5);
+            assert!((c[2] - 1.).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn reduces_noise() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let color = (0..32 * 32)
+            .map(|_| {
+                let v = rng.gen_range(0.0..1.0);
+                [v, v, v]
+            })
+            .collect::<Vec<_>>();
+        let frame = frame(32, 32, &[[0., 0., -1.]; 32 * 32], &color);
+        let denoised = denoise(&frame, &DenoiseSettings::default());
+        assert!(variance(&denoised) < variance(&color) * 0.25);
+    }
+
+    #[test]
+    fn preserves_normal_edges() {
+        let (width, height) = (16, 16);
+        let left = |i: usize| i % width < width / 2;
+        let normal = (0..width * height)
+            .map(|i| if left(i) { [1., 0., 0.] } else { [0., 1., 0.] })
+            .collect::<Vec<_>>();
+        let color = (0..width * height)
+            .map(|i| if left(i) { [1.; 3] } else { [0.; 3] })
+            .collect::<Vec<_>>();
+        let frame = frame(width as u32, height as u32, &normal, &color);
+        let denoised = denoise(&frame, &DenoiseSettings::default());
+        for (i, c) in denoised.iter().enumerate() {
+            let expected = if left(i) { 1. } else { 0. };
+            assert!((c[0] - expected).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+// denoisers selectable with params.mode
+let MODE_ATROUS: u32 = 1u;
+let MODE_SVGF: u32 = 2u;
+
+// frames with less samples or history estimate the variance spatially
+let MIN_VARIANCE_SAMPLES: f32 = 4.0;
+let MAX_HISTORY: f32 = 32.0;
+// relative depth difference at which the history is rejected
+let DEPTH_TOLERANCE: f32 = 0.05;
+
+// latest accumulation of the frame buffer, see compute.wgsl
+[[group(0), binding(0)]]
+var frame_color: texture_2d<f32>;
+
+[[group(0), binding(1)]]
+var frame_albedo: texture_2d<f32>;
+
+[[group(0), binding(2)]]
+var frame_normal: texture_2d<f32>;
+
+[[group(0), binding(3)]]
+var frame_position: texture_2d<f32>;
+
+[[group(0), binding(4)]]
+var frame_ids: texture_2d<u32>;
+
+// sum of the squared sample luminance
+[[group(0), binding(5)]]
+var frame_moments: texture_2d<f32>;
+
+[[block]]
+struct Params {
+    // transforms world positions into the camera space of the previous frame
+    prev_world_to_camera: mat4x4<f32>;
+    mode: u32;
+    // distance between the taps of the à-trous kernel
+    step: u32;
+    // the last iteration writes the remodulated color to the output
+    last: u32;
+    // the camera moved since the previous frame
+    moved: u32;
+    sigma_color: f32;
+    sigma_normal: f32;
+    sigma_depth: f32;
+    alpha_min: f32;
+};
+
+[[group(1), binding(0)]]
+var<uniform> params: Params;
+
+// rgb: illumination without albedo, a: variance of its luminance
+[[group(2), binding(0)]]
+var src: texture_2d<f32>;
+
+[[group(2), binding(1)]]
+var dst: [[access(write)]] texture_storage_2d<rgba32float>;
+
+// rgb: temporally accumulated illumination, a: history length in samples
+[[group(2), binding(2)]]
+var history_src: texture_2d<f32>;
+
+[[group(2), binding(3)]]
+var history_dst: [[access(write)]] texture_storage_2d<rgba32float>;
+
+// r, g: first and second moment of the luminance, b: depth, a: object id
+[[group(2), binding(4)]]
+var moments_src: texture_2d<f32>;
+
+[[group(2), binding(5)]]
+var moments_dst: [[access(write)]] texture_storage_2d<rgba32float>;
+
+// denoised color in the frame buffer format
+[[group(2), binding(6)]]
+var output: [[access(write)]] texture_storage_2d<rgba32float>;
+
+fn luminance(rgb: vec3<f32>) -> f32 {
+    return dot(rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn samples(pix: vec2<i32>) -> f32 {
+    return max(textureLoad(frame_color, pix, 0).a, 1.0);
+}
+
+// the albedo is divided out before filtering so that texture detail is kept,
+// channels without albedo are filtered as they are
+fn demodulation(pix: vec2<i32>) -> vec3<f32> {
+    let albedo = textureLoad(frame_albedo, pix, 0).rgb / samples(pix);
+    return vec3<f32>(
+        select(albedo.x, 1.0, albedo.x < 0.001),
+        select(albedo.y, 1.0, albedo.y < 0.001),
+        select(albedo.z, 1.0, albedo.z < 0.001),
+    );
+}
+
+fn normal(pix: vec2<i32>) -> vec3<f32> {
+    let n = textureLoad(frame_normal, pix, 0).xyz;
+    let l = length(n);
+    if (l > 0.0) {
+        return n / l;
+    }
+    return n;
+}
+
+fn depth(pix: vec2<i32>) -> f32 {
+    return textureLoad(frame_position, pix, 0).w / samples(pix);
+}
+
+fn illumination(pix: vec2<i32>) -> vec3<f32> {
+    return textureLoad(frame_color, pix, 0).rgb / samples(pix) / demodulation(pix);
+}
+
+fn inside(pix: vec2<i32>, size: vec2<i32>) -> bool {
+    return pix.x >= 0 && pix.y >= 0 && pix.x < size.x && pix.y < size.y;
+}
+
+// variance of the mean luminance estimated from the squared sample luminance
+fn sample_variance(pix: vec2<i32>) -> f32 {
+    let n = samples(pix);
+    let color = textureLoad(frame_color, pix, 0).rgb / n;
+    let mean = luminance(color);
+    let mean_squared = textureLoad(frame_moments, pix, 0).x / n;
+    let d = luminance(demodulation(pix));
+    return max(mean_squared - mean * mean, 0.0) / n / (d * d);
+}
+
+// variance of the luminance in the 3x3 neighbourhood on the same object
+fn spatial_variance(pix: vec2<i32>, size: vec2<i32>) -> f32 {
+    let object = textureLoad(frame_ids, pix, 0).g;
+    var sum: f32 = 0.0;
+    var sum_squared: f32 = 0.0;
+    var count: f32 = 0.0;
+    for (var y: i32 = -1; y <= 1; y = y + 1) {
+        for (var x: i32 = -1; x <= 1; x = x + 1) {
+            let q = pix + vec2<i32>(x, y);
+            if (inside(q, size) && textureLoad(frame_ids, q, 0).g == object) {
+                let l = luminance(illumination(q));
+                sum = sum + l;
+                sum_squared = sum_squared + l * l;
+                count = count + 1.0;
+            }
+        }
+    }
+    let mean = sum / count;
+    return max(sum_squared / count - mean * mean, 0.0);
+}
+
+// demodulates the frame buffer, the svgf mode also accumulates it over time
+[[stage(compute), workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)]]
+fn prepare([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(frame_color));
+    let pix = vec2<i32>(gid.xy);
+    if (!inside(pix, size)) {
+        return;
+    }
+
+    let n = samples(pix);
+    let current = illumination(pix);
+    var variance: f32 = sample_variance(pix);
+    if (params.mode != MODE_SVGF) {
+        if (n < MIN_VARIANCE_SAMPLES) {
+            variance = spatial_variance(pix, size);
+        }
+        textureStore(dst, pix, vec4<f32>(current, variance));
+        return;
+    }
+
+    // find the pixel of the previous frame showing the same surface
+    let object = textureLoad(frame_ids, pix, 0).g;
+    let position = textureLoad(frame_position, pix, 0).xyz / n;
+    let q = (params.prev_world_to_camera * vec4<f32>(position, 1.0)).xyz;
+    var history: vec4<f32> = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    var moments: vec2<f32> = vec2<f32>(0.0, 0.0);
+    var valid: bool = false;
+    if (object != 0u && q.z > 0.0) {
+        let ratio = f32(size.x) / f32(size.y);
+        let uv = vec2<f32>(q.x / q.z / ratio + 0.5, -q.y / q.z + 0.5);
+        let prev = vec2<i32>(floor(uv * vec2<f32>(size)));
+        if (inside(prev, size)) {
+            let prev_moments = textureLoad(moments_src, prev, 0);
+            if (u32(prev_moments.a) == object && abs(prev_moments.b - q.z) < DEPTH_TOLERANCE * q.z) {
+                history = textureLoad(history_src, prev, 0);
+                moments = prev_moments.rg;
+                valid = true;
+            }
+        }
+    }
+
+    // a moving camera restarts the accumulation, so the history holds more samples.
+    // Otherwise the accumulation contains the history and replaces it once it has more samples
+    var history_length: f32 = n;
+    var alpha: f32 = 1.0;
+    if (valid && params.moved != 0u) {
+        history_length = min(history.a + n, MAX_HISTORY);
+        alpha = max(n / history_length, params.alpha_min);
+    } elseif (valid) {
+        history_length = max(history.a, n);
+        alpha = n / history_length;
+    }
+
+    let l = luminance(current);
+    let integrated = mix(history.rgb, current, vec3<f32>(alpha));
+    let integrated_moments = mix(moments, vec2<f32>(l, l * l), vec2<f32>(alpha));
+    textureStore(history_dst, pix, vec4<f32>(integrated, history_length));
+    textureStore(moments_dst, pix, vec4<f32>(integrated_moments, depth(pix), f32(object)));
+
+    if (n < MIN_VARIANCE_SAMPLES) {
+        if (history_length < MIN_VARIANCE_SAMPLES) {
+            variance = spatial_variance(pix, size);
+        } else {
+            variance = max(integrated_moments.y - integrated_moments.x * integrated_moments.x, 0.0);
+        }
+    }
+    textureStore(dst, pix, vec4<f32>(integrated, variance));
+}
+
+// weights of the 5 tap B3 spline
+fn kernel(offset: i32) -> f32 {
+    if (offset == 0) {
+        return 0.375;
+    }
+    if (offset == 1 || offset == -1) {
+        return 0.25;
+    }
+    return 0.0625;
+}
+
+// standard deviation after a 3x3 gaussian blur of the variance
+fn filtered_deviation(pix: vec2<i32>, size: vec2<i32>) -> f32 {
+    var sum: f32 = 0.0;
+    var weights: f32 = 0.0;
+    for (var y: i32 = -1; y <= 1; y = y + 1) {
+        for (var x: i32 = -1; x <= 1; x = x + 1) {
+            let q = pix + vec2<i32>(x, y);
+            if (inside(q, size)) {
+                let w = (2.0 - f32(abs(x))) * (2.0 - f32(abs(y)));
+                sum = sum + w * textureLoad(src, q, 0).a;
+                weights = weights + w;
+            }
+        }
+    }
+    return sqrt(sum / weights);
+}
+
+// one iteration of the edge-avoiding à-trous wavelet transform (Dammertz et al. 2010),
+// the luminance weight is guided by the variance like in svgf (Schied et al. 2017)
+[[stage(compute), workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE)]]
+fn atrous([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(frame_color));
+    let pix = vec2<i32>(gid.xy);
+    if (!inside(pix, size)) {
+        return;
+    }
+
+    let center = textureLoad(src, pix, 0);
+    let n_p = normal(pix);
+    let d_p = depth(pix);
+    let l_p = luminance(center.rgb);
+    let spacing = i32(params.step);
+    let deviation = filtered_deviation(pix, size);
+
+    var color: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
+    var variance: f32 = 0.0;
+    var weights: f32 = 0.0;
+    for (var y: i32 = -2; y <= 2; y = y + 1) {
+        for (var x: i32 = -2; x <= 2; x = x + 1) {
+            let q = pix + vec2<i32>(x, y) * spacing;
+            if (inside(q, size)) {
+                let tap = textureLoad(src, q, 0);
+                var w: f32 = kernel(x) * kernel(y);
+                if (x != 0 || y != 0) {
+                    let w_normal = pow(max(dot(n_p, normal(q)), 0.0), params.sigma_normal);
+                    let w_depth = exp(-abs(d_p - depth(q)) / (params.sigma_depth * f32(spacing) * d_p + 0.000001));
+                    let w_color = exp(-abs(l_p - luminance(tap.rgb)) / (params.sigma_color * deviation + 0.000001));
+                    w = w * w_normal * w_depth * w_color;
+                }
+                color = color + w * tap.rgb;
+                variance = variance + w * w * tap.a;
+                weights = weights + w;
+            }
+        }
+    }
+    color = color / weights;
+    variance = variance / (weights * weights);
+
+    if (params.last != 0u) {
+        textureStore(output, pix, vec4<f32>(color * demodulation(pix), 1.0));
+    } else {
+        textureStore(dst, pix, vec4<f32>(color, variance));
+    }
+}
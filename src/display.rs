@@ -0,0 +1,305 @@
+use crate::settings::{DisplaySettings, ToneMapper};
+
+/// Uniforms of display.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DisplayUniforms {
+    white_balance: [f32; 3],
+    exposure: f32,
+    tone_mapper: u32,
+    white_point: f32,
+    /// size of the window in pixels
+    viewport: [f32; 2],
+    upscale: u32,
+    _padding: [u32; 3],
+    /// outlined rectangle in frame buffer pixels: min x, min y, max x, max y
+    roi: [u32; 4],
+}
+
+impl DisplayUniforms {
+    pub fn new(settings: &DisplaySettings) -> Self {
+        Self {
+            white_balance: white_balance_gains(settings.white_balance),
+            exposure: settings.exposure,
+            tone_mapper: settings.tone_mapper as u32,
+            white_point: settings.white_point,
+            viewport: [1.0; 2],
+            upscale: settings.upscale as u32,
+            _padding: [0; 3],
+            roi: [0; 4],
+        }
+    }
+
+    /// the frame buffer is stretched over the window
+    pub fn with_viewport(self, width: u32, height: u32) -> Self {
+        Self {
+            viewport: [width as f32, height as f32],
+            ..self
+        }
+    }
+
+    /// outlines a rectangle given as x, y, width and height in frame buffer pixels
+    pub fn with_roi(self, roi: [u32; 4]) -> Self {
+        Self {
+            roi: [roi[0], roi[1], roi[0] + roi[2], roi[1] + roi[3]],
+            ..self
+        }
+    }
+
+    /// cpu version of fs_main in display.wgsl, maps a mean radiance to an 8 bit sRGB color
+    pub fn apply(&self, rgb: [f32; 3]) -> [u8; 3] {
+        let scale = self.exposure.exp2();
+        let exposed = [
+            rgb[0] * self.white_balance[0] * scale,
+            rgb[1] * self.white_balance[1] * scale,
+            rgb[2] * self.white_balance[2] * scale,
+        ];
+        let mapped = match self.tone_mapper {
+            t if t == ToneMapper::Reinhard as u32 => reinhard(exposed, self.white_point),
+            t if t == ToneMapper::Aces as u32 => aces_fitted(exposed),
+            t if t == ToneMapper::Hable as u32 => hable(exposed),
+            t if t == ToneMapper::AgX as u32 => agx(exposed),
+            _ => exposed,
+        };
+        [
+            to_srgb8(mapped[0]),
+            to_srgb8(mapped[1]),
+            to_srgb8(mapped[2]),
+        ]
+    }
+}
+
+/// encodes a linear value to 8 bit sRGB
+pub fn to_srgb8(value: f32) -> u8 {
+    let c = value.max(0.).min(1.);
+    let encoded = if c < 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    };
+    (encoded * 255. + 0.5) as u8
+}
+
+fn luminance(rgb: [f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+fn map3<F: Fn(f32) -> f32>(rgb: [f32; 3], f: F) -> [f32; 3] {
+    [f(rgb[0]), f(rgb[1]), f(rgb[2])]
+}
+
+fn mul3(m: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
+
+// the tone mapping operators below match the ones in display.wgsl
+
+fn reinhard(rgb: [f32; 3], white_point: f32) -> [f32; 3] {
+    let l = luminance(rgb);
+    if l <= 0. {
+        return rgb;
+    }
+    let l_mapped = l * (1. + l / (white_point * white_point)) / (1. + l);
+    map3(rgb, |c| c * l_mapped / l)
+}
+
+fn aces_fitted(rgb: [f32; 3]) -> [f32; 3] {
+    let input = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    let output = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let fitted = map3(mul3(&input, rgb), |v| {
+        (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.432951) + 0.238081)
+    });
+    mul3(&output, fitted)
+}
+
+fn hable_partial(x: f32) -> f32 {
+    let shoulder = 0.15;
+    let linear = 0.50;
+    let angle = 0.10;
+    let toe = 0.20;
+    let toe_numerator = 0.02;
+    let toe_denominator = 0.30;
+    ((x * (shoulder * x + angle * linear) + toe * toe_numerator)
+        / (x * (shoulder * x + linear) + toe * toe_denominator))
+        - toe_numerator / toe_denominator
+}
+
+fn hable(rgb: [f32; 3]) -> [f32; 3] {
+    let exposure_bias = 2.;
+    let white = hable_partial(11.2);
+    map3(rgb, |c| hable_partial(c * exposure_bias) / white)
+}
+
+fn agx_contrast(x: f32) -> f32 {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+        - 0.00232
+}
+
+fn agx(rgb: [f32; 3]) -> [f32; 3] {
+    let (min_ev, max_ev) = (-12.47393, 4.026069);
+    // rows of the column major matrices in display.wgsl
+    let inset = [
+        [0.8424791, 0.0784336, 0.07922375],
+        [0.04232824, 0.8784686, 0.07916613],
+        [0.04237565, 0.0784336, 0.879143],
+    ];
+    let outset = [
+        [1.196879, -0.09802088, -0.09902974],
+        [-0.05289685, 1.151903, -0.09896118],
+        [-0.05297164, -0.09804345, 1.151074],
+    ];
+    let encoded = map3(mul3(&inset, rgb), |c| {
+        let log_color = c.max(1e-10).log2().max(min_ev).min(max_ev);
+        agx_contrast((log_color - min_ev) / (max_ev - min_ev))
+    });
+    map3(mul3(&outset, encoded), |c| c.max(0.).powf(2.2))
+}
+
+/// chromaticity of the planckian locus, approximation by Kim et al. (2002).
+/// Clamped to 2000K as the blue channel of lower temperatures is outside of sRGB
+fn planckian_xy(temperature: f32) -> (f32, f32) {
+    let t = temperature.max(2000.).min(25000.) as f64;
+    let x = if t <= 4000. {
+        -0.2661239e9 / t.powi(3) - 0.2343589e6 / t.powi(2) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.025847e9 / t.powi(3) + 2.107038e6 / t.powi(2) + 0.2226347e3 / t + 0.240390
+    };
+    let y = if t <= 2222. {
+        -1.106381 * x.powi(3) - 1.34811 * x.powi(2) + 2.185558 * x - 0.2021968
+    } else if t <= 4000. {
+        -0.9549476 * x.powi(3) - 1.374186 * x.powi(2) + 2.09137 * x - 0.1674887
+    } else {
+        3.081758 * x.powi(3) - 5.873387 * x.powi(2) + 3.75113 * x - 0.3700148
+    };
+    (x as f32, y as f32)
+}
+
+/// linear sRGB color of a black body with luminance 1
+fn black_body_rgb(temperature: f32) -> [f32; 3] {
+    let (x, y) = planckian_xy(temperature);
+    let xyz = [x / y, 1., (1. - x - y) / y];
+    [
+        3.2406 * xyz[0] - 1.5372 * xyz[1] - 0.4986 * xyz[2],
+        -0.9689 * xyz[0] + 1.8758 * xyz[1] + 0.0415 * xyz[2],
+        0.0557 * xyz[0] - 0.2040 * xyz[1] + 1.0570 * xyz[2],
+    ]
+}
+
+/// per channel gains which map the color of a black body with the given temperature
+/// to white (relative to 6500K) while preserving the luminance
+pub fn white_balance_gains(temperature: f32) -> [f32; 3] {
+    let reference = black_body_rgb(6500.);
+    let white = black_body_rgb(temperature);
+    let gains = [
+        reference[0] / white[0].max(1e-4),
+        reference[1] / white[1].max(1e-4),
+        reference[2] / white[2].max(1e-4),
+    ];
+    let luminance = 0.2126 * gains[0] + 0.7152 * gains[1] + 0.0722 * gains[2];
+    [
+        gains[0] / luminance,
+        gains[1] / luminance,
+        gains[2] / luminance,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// radiance mapped by every operator below, the references are evaluated in double
+    /// precision from the expressions and column major matrices of display.wgsl
+    const RGB: [f32; 3] = [0.18, 0.5, 2.0];
+
+    fn assert_close(mapped: [f32; 3], reference: [f32; 3]) {
+        for (m, r) in mapped.iter().zip(reference.iter()) {
+            assert!(
+                (m - r).abs() < 1e-4,
+                "{:?} instead of {:?}",
+                mapped,
+                reference
+            );
+        }
+    }
+
+    #[test]
+    fn maps_reinhard() {
+        assert_close(reinhard(RGB, 4.), [0.120809, 0.335580, 1.342321]);
+        assert_close(reinhard([0.; 3], 4.), [0.; 3]);
+        // the white point is mapped to 1
+        assert_close(reinhard([4.; 3], 4.), [1.; 3]);
+    }
+
+    #[test]
+    fn maps_aces() {
+        assert_close(aces_fitted(RGB), [0.198835, 0.380545, 0.805161]);
+    }
+
+    #[test]
+    fn maps_hable() {
+        assert_close(hable(RGB), [0.128338, 0.304301, 0.713238]);
+        // the white of 11.2 is halved by the exposure bias
+        assert_close(hable([5.6; 3]), [1.; 3]);
+    }
+
+    #[test]
+    fn maps_agx() {
+        assert_close(agx(RGB), [0.303961, 0.455515, 0.775298]);
+    }
+
+    #[test]
+    fn encodes_srgb() {
+        let encoded: Vec<u8> = [-1., 0., 0.0015, 0.18, 0.5, 1., 2.]
+            .iter()
+            .map(|&v| to_srgb8(v))
+            .collect();
+        assert_eq!(encoded, [0, 0, 5, 118, 188, 255, 255]);
+    }
+
+    #[test]
+    fn applies_the_tone_mapper() {
+        let expected = [
+            (ToneMapper::Clamp, [118, 188, 255]),
+            (ToneMapper::Reinhard, [97, 157, 255]),
+            (ToneMapper::Aces, [123, 166, 232]),
+            (ToneMapper::Hable, [100, 150, 220]),
+            (ToneMapper::AgX, [150, 180, 228]),
+        ];
+        for &(tone_mapper, srgb) in expected.iter() {
+            let settings = DisplaySettings {
+                tone_mapper,
+                white_point: 4.,
+                ..DisplaySettings::default()
+            };
+            assert_eq!(
+                DisplayUniforms::new(&settings).apply(RGB),
+                srgb,
+                "{:?}",
+                tone_mapper
+            );
+        }
+        // one stop doubles the radiance
+        let settings = DisplaySettings {
+            exposure: 1.,
+            ..DisplaySettings::default()
+        };
+        assert_eq!(
+            DisplayUniforms::new(&settings).apply([0.09, 0.25, 0.]),
+            [118, 188, 0]
+        );
+    }
+}
@@ -7,6 +7,37 @@ let positions: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
     vec2<f32>(-1.0, -1.0),
 );
 
+// tone mapping operators selectable with display.tone_mapper
+let TONEMAP_CLAMP: u32 = 0u;
+let TONEMAP_REINHARD: u32 = 1u;
+let TONEMAP_ACES: u32 = 2u;
+let TONEMAP_HABLE: u32 = 3u;
+let TONEMAP_AGX: u32 = 4u;
+
+// filters of the frame buffer selectable with display.upscale
+let UPSCALE_NEAREST: u32 = 0u;
+let UPSCALE_BILINEAR: u32 = 1u;
+
+[[group(0), binding(0)]]
+var texture: [[access(read)]] texture_storage_2d<rgba32float>;
+
+[[block]]
+struct Display {
+    white_balance: vec3<f32>;
+    // exposure in stops
+    exposure: f32;
+    tone_mapper: u32;
+    white_point: f32;
+    // size of the window in pixels
+    viewport: vec2<f32>;
+    upscale: u32;
+    // outlined region of interest in frame buffer pixels: min x, min y, max x, max y
+    roi: vec4<u32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> display: Display;
+
 [[stage(vertex)]]
 fn vs_main([[builtin(vertex_index)]] in_vertex_index: u32) -> [[builtin(position)]] vec4<f32> {
     return vec4<f32>(positions[in_vertex_index], 0.0, 1.0);
@@ -29,15 +60,145 @@ fn linearToSRGB(rgb:vec3<f32>) -> vec3<f32> {
                lessThan(rgb_c, 0.0031308));
 }
 
-[[group(0), binding(0)]]
-var texture: [[access(read)]] texture_storage_2d<rgba16float>;
+fn luminance(rgb: vec3<f32>) -> f32 {
+    return dot(rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// extended reinhard applied to the luminance, white_point is mapped to 1
+fn reinhard(rgb: vec3<f32>, white_point: f32) -> vec3<f32> {
+    let l = luminance(rgb);
+    if (l <= 0.0) {
+        return rgb;
+    }
+    let l_mapped = l * (1.0 + l / (white_point * white_point)) / (1.0 + l);
+    return rgb * (l_mapped / l);
+}
+
+// source: Stephen Hill, https://github.com/TheRealMJP/BakingLab/blob/master/BakingLab/ACES.hlsl
+fn aces_fitted(rgb: vec3<f32>) -> vec3<f32> {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    let v = vec3<f32>(
+        dot(vec3<f32>(0.59719, 0.35458, 0.04823), rgb),
+        dot(vec3<f32>(0.07600, 0.90834, 0.01566), rgb),
+        dot(vec3<f32>(0.02840, 0.13383, 0.83777), rgb),
+    );
+    // RRT and ODT fit
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    let fitted = a / b;
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    return vec3<f32>(
+        dot(vec3<f32>(1.60475, -0.53108, -0.07367), fitted),
+        dot(vec3<f32>(-0.10208, 1.10813, -0.00605), fitted),
+        dot(vec3<f32>(-0.00327, -0.07276, 1.07602), fitted),
+    );
+}
+
+// source: John Hable, filmic tone mapping of Uncharted 2
+fn hable_partial(x: vec3<f32>) -> vec3<f32> {
+    let A = 0.15;
+    let B = 0.50;
+    let C = 0.10;
+    let D = 0.20;
+    let E = 0.02;
+    let F = 0.30;
+    return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
+}
+
+fn hable(rgb: vec3<f32>) -> vec3<f32> {
+    let exposure_bias = 2.0;
+    let white = vec3<f32>(11.2, 11.2, 11.2);
+    return hable_partial(rgb * exposure_bias) / hable_partial(white);
+}
+
+// source: https://iolite-engine.com/blog_posts/minimal_agx_implementation
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2
+        - 40.14 * x4 * x
+        + 31.96 * x4
+        - 6.868 * x2 * x
+        + 0.4298 * x2
+        + 0.1191 * x
+        - 0.00232;
+}
+
+fn agx(rgb: vec3<f32>) -> vec3<f32> {
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
 
+    let log_color = clamp(log2(max(inset * rgb, vec3<f32>(0.0000000001))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    let encoded = agx_contrast((log_color - min_ev) / (max_ev - min_ev));
+    // back to linear, the result is encoded to sRGB afterwards
+    return pow(max(outset * encoded, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn tonemap(rgb: vec3<f32>) -> vec3<f32> {
+    if (display.tone_mapper == TONEMAP_REINHARD) {
+        return reinhard(rgb, display.white_point);
+    }
+    if (display.tone_mapper == TONEMAP_ACES) {
+        return aces_fitted(rgb);
+    }
+    if (display.tone_mapper == TONEMAP_HABLE) {
+        return hable(rgb);
+    }
+    if (display.tone_mapper == TONEMAP_AGX) {
+        return agx(rgb);
+    }
+    return rgb;
+}
+
+// mean radiance of a texel, coordinates outside of the frame buffer are clamped
+fn texel(pix: vec2<i32>, size: vec2<i32>) -> vec3<f32> {
+    let p = max(min(pix, size - vec2<i32>(1, 1)), vec2<i32>(0, 0));
+    let accumulated = textureLoad(texture, p);
+    // alpha holds the number of samples
+    return accumulated.rgb / max(accumulated.a, 1.0);
+}
 
 [[stage(fragment)]]
 fn fs_main([[builtin(position)]] position: vec4<f32>) -> [[location(0)]] vec4<f32> {
     let size = textureDimensions(texture);
-    let x = i32(position.x);
-    let y = i32(position.y);
-	let color = textureLoad(texture, vec2<i32>(x, y));
-    return vec4<f32>(linearToSRGB(color.rgb),color.a);
-}
\ No newline at end of file
+    // position in frame buffer pixels, the frame buffer is stretched over the window
+    let pos = position.xy / display.viewport * vec2<f32>(size);
+    var color: vec3<f32>;
+    if (display.upscale == UPSCALE_BILINEAR) {
+        let p = pos - vec2<f32>(0.5, 0.5);
+        let base = floor(p);
+        let f = p - base;
+        let i = vec2<i32>(base);
+        let top = mix(texel(i, size), texel(i + vec2<i32>(1, 0), size), vec3<f32>(f.x));
+        let bottom = mix(texel(i + vec2<i32>(0, 1), size), texel(i + vec2<i32>(1, 1), size), vec3<f32>(f.x));
+        color = mix(top, bottom, vec3<f32>(f.y));
+    } else {
+        color = texel(vec2<i32>(pos), size);
+    }
+	let exposed = color * display.white_balance * exp2(display.exposure);
+
+    // one window pixel wide outline of the region of interest
+    if (display.roi.z > display.roi.x) {
+        let scale = display.viewport / vec2<f32>(size);
+        let lower = vec2<f32>(display.roi.xy) * scale;
+        let upper = vec2<f32>(display.roi.zw) * scale;
+        let p = position.xy;
+        let inside = p.x >= lower.x - 1.0 && p.y >= lower.y - 1.0 && p.x <= upper.x + 1.0 && p.y <= upper.y + 1.0;
+        let interior = p.x > lower.x && p.y > lower.y && p.x < upper.x && p.y < upper.y;
+        if (inside && !interior) {
+            return vec4<f32>(1.0, 0.8, 0.0, 1.0);
+        }
+    }
+    return vec4<f32>(linearToSRGB(tonemap(exposed)), 1.0);
+}
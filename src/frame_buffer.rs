@@ -0,0 +1,711 @@
+use std::{future::Future, iter, num::NonZeroU32, pin::Pin};
+
+use futures::FutureExt;
+use wgpu::{util::DeviceExt, Texture};
+
+/// Format of the frame buffer, rgb holds the sum of all samples and alpha the number of samples
+pub const FRAME_BUFFER_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+/// Format of the accumulated AOVs, holds the sum of all samples
+pub const AOV_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+/// Accumulated arbitrary output variables of the first hit, in binding order
+pub const AOV_NAMES: [&str; 3] = ["albedo", "normal", "position"];
+
+/// Format of the id texture, r holds the material and g the object id of the first hit
+pub const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Uint;
+
+/// Format of the moments, the sum of the squared sample luminance used to estimate the variance
+pub const MOMENTS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+pub fn create_empty_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    usage: wgpu::TextureUsage,
+) -> wgpu::Texture {
+    let data = vec![0u8; (width * height * (format.describe().block_size as u32)) as usize];
+    let d = data.as_slice();
+    device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some("Output Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::STORAGE | usage,
+        },
+        d,
+    )
+}
+
+/// Copies a texture to the cpu and returns its tightly packed texels
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+) -> Vec<u8> {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("read back encoder"),
+    });
+    let copy = TextureCopy::encode(device, &mut encoder, texture, width, height, format);
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let mapping = copy.buffer.slice(..).map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    pollster::block_on(mapping).expect("failed to map the read back buffer");
+    copy.texels()
+}
+
+/// Pending mapping of a read back buffer
+type Mapping = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+/// Texture copied into a buffer which can be mapped once the copy was submitted
+struct TextureCopy {
+    buffer: wgpu::Buffer,
+    row_size: u32,
+    padded_row_size: u32,
+}
+
+impl TextureCopy {
+    fn encode(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let texel_size = format.describe().block_size as u32;
+        let row_size = width * texel_size;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row_size = (row_size + align - 1) / align * align;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("read back buffer"),
+            size: (padded_row_size * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_row_size),
+                    rows_per_image: NonZeroU32::new(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        Self {
+            buffer,
+            row_size,
+            padded_row_size,
+        }
+    }
+
+    /// the tightly packed texels, the buffer has to be mapped
+    fn texels(&self) -> Vec<u8> {
+        let slice = self.buffer.slice(..);
+        let mut texels = Vec::new();
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(self.padded_row_size as usize) {
+                texels.extend_from_slice(&row[..self.row_size as usize]);
+            }
+        }
+        self.buffer.unmap();
+        texels
+    }
+}
+
+/// Copy of the frame buffer on its way to the cpu, recorded by `FrameBuffer::encode_read_back`.
+/// The data is available once the gpu finished the copies, which is only noticed while
+/// the device is polled
+pub struct ReadBack {
+    width: u32,
+    height: u32,
+    color: TextureCopy,
+    aovs: Vec<TextureCopy>,
+    ids: TextureCopy,
+    moments: TextureCopy,
+    /// None until the copies were submitted and the buffers are mapped
+    mappings: Option<Vec<Mapping>>,
+}
+
+impl ReadBack {
+    fn copies(&self) -> impl Iterator<Item = &TextureCopy> {
+        iter::once(&self.color)
+            .chain(self.aovs.iter())
+            .chain(iter::once(&self.ids))
+            .chain(iter::once(&self.moments))
+    }
+
+    /// Returns the data if the copies are done, otherwise starts mapping the buffers.
+    /// Must not be called before the encoder of the copies was submitted
+    pub fn try_finish(&mut self) -> Option<FrameBufferData> {
+        if self.mappings.is_none() {
+            let mappings = self
+                .copies()
+                .map(|copy| {
+                    Box::pin(copy.buffer.slice(..).map_async(wgpu::MapMode::Read)) as Mapping
+                })
+                .collect();
+            self.mappings = Some(mappings);
+        }
+        let mappings = self.mappings.as_mut().unwrap();
+        let mut pending = Vec::new();
+        for mut mapping in mappings.drain(..) {
+            match (&mut mapping).now_or_never() {
+                Some(result) => result.expect("failed to map the read back buffer"),
+                None => pending.push(mapping),
+            }
+        }
+        *mappings = pending;
+        if mappings.is_empty() {
+            Some(self.data())
+        } else {
+            None
+        }
+    }
+
+    /// blocks until the copies are done
+    pub fn finish(mut self, device: &wgpu::Device) -> FrameBufferData {
+        loop {
+            if let Some(data) = self.try_finish() {
+                return data;
+            }
+            device.poll(wgpu::Maintain::Wait);
+        }
+    }
+
+    fn data(&self) -> FrameBufferData {
+        FrameBufferData {
+            width: self.width,
+            height: self.height,
+            color: FrameBufferData::parse_f32(&self.color.texels()),
+            aovs: self
+                .aovs
+                .iter()
+                .map(|copy| FrameBufferData::parse_f32(&copy.texels()))
+                .collect(),
+            ids: FrameBufferData::parse_u32(&self.ids.texels()),
+            moments: FrameBufferData::parse_r32(&self.moments.texels()),
+        }
+    }
+}
+
+fn to_words(bytes: &[u8]) -> impl Iterator<Item = [u8; 4]> + '_ {
+    bytes.chunks_exact(4).map(|w| [w[0], w[1], w[2], w[3]])
+}
+
+/// Frame buffer contents copied to the cpu, all values are sums over the samples of a pixel
+pub struct FrameBufferData {
+    pub width: u32,
+    pub height: u32,
+    /// rgb: radiance, a: number of samples
+    pub color: Vec<[f32; 4]>,
+    /// accumulated AOVs in the order of AOV_NAMES
+    pub aovs: Vec<Vec<[f32; 4]>>,
+    /// material and object id of the first hit
+    pub ids: Vec<[u32; 2]>,
+    /// sum of the squared sample luminance
+    pub moments: Vec<f32>,
+}
+
+impl FrameBufferData {
+    /// frame buffer without samples, `aovs` is the number of AOVs
+    pub fn new(width: u32, height: u32, aovs: usize) -> Self {
+        let pixels = width as usize * height as usize;
+        Self {
+            width,
+            height,
+            color: vec![[0.; 4]; pixels],
+            aovs: vec![vec![[0.; 4]; pixels]; aovs],
+            ids: vec![[0; 2]; pixels],
+            moments: vec![0.; pixels],
+        }
+    }
+
+    /// copies a tile into the frame buffer with its upper left corner at x, y.
+    /// AOVs which are missing in either of them are skipped
+    pub fn paste(&mut self, tile: &FrameBufferData, x: u32, y: u32) {
+        assert!(
+            x + tile.width <= self.width && y + tile.height <= self.height,
+            "the tile is outside of the frame buffer"
+        );
+        let (width, tile_width) = (self.width as usize, tile.width as usize);
+        for row in 0..tile.height as usize {
+            let src = row * tile_width..(row + 1) * tile_width;
+            let start = (y as usize + row) * width + x as usize;
+            let dst = start..start + tile_width;
+            self.color[dst.clone()].copy_from_slice(&tile.color[src.clone()]);
+            for (aov, tile_aov) in self.aovs.iter_mut().zip(tile.aovs.iter()) {
+                aov[dst.clone()].copy_from_slice(&tile_aov[src.clone()]);
+            }
+            self.ids[dst.clone()].copy_from_slice(&tile.ids[src.clone()]);
+            self.moments[dst].copy_from_slice(&tile.moments[src]);
+        }
+    }
+
+    /// parses little endian rgba32float texels
+    pub fn parse_f32(bytes: &[u8]) -> Vec<[f32; 4]> {
+        let values: Vec<f32> = to_words(bytes).map(f32::from_le_bytes).collect();
+        values
+            .chunks_exact(4)
+            .map(|c| [c[0], c[1], c[2], c[3]])
+            .collect()
+    }
+
+    /// parses little endian r32float texels
+    pub fn parse_r32(bytes: &[u8]) -> Vec<f32> {
+        to_words(bytes).map(f32::from_le_bytes).collect()
+    }
+
+    /// parses little endian rg32uint texels
+    pub fn parse_u32(bytes: &[u8]) -> Vec<[u32; 2]> {
+        let values: Vec<u32> = to_words(bytes).map(u32::from_le_bytes).collect();
+        values.chunks_exact(2).map(|c| [c[0], c[1]]).collect()
+    }
+
+    /// number of samples of a pixel
+    pub fn samples(&self, index: usize) -> f32 {
+        self.color[index][3]
+    }
+
+    /// mean radiance of every pixel
+    pub fn mean_color(&self) -> Vec<[f32; 3]> {
+        self.color
+            .iter()
+            .map(|c| {
+                let n = c[3].max(1.);
+                [c[0] / n, c[1] / n, c[2] / n]
+            })
+            .collect()
+    }
+
+    /// replaces the radiance while keeping the number of samples
+    pub fn set_mean_color(&mut self, colors: &[[f32; 3]]) {
+        for (c, mean) in self.color.iter_mut().zip(colors.iter()) {
+            let n = c[3].max(1.);
+            *c = [mean[0] * n, mean[1] * n, mean[2] * n, c[3]];
+        }
+    }
+
+    /// Mean relative standard error of the pixel luminance, the same estimate as
+    /// used by the adaptive sampling
+    pub fn mean_relative_error(&self) -> f32 {
+        let sum: f32 = self
+            .color
+            .iter()
+            .zip(self.moments.iter())
+            .map(|(c, squared)| {
+                let n = c[3].max(1.);
+                let mean = (0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2]) / n;
+                let variance = (squared / n - mean.powi(2)).max(0.);
+                // the offset keeps dark pixels from dominating
+                (variance / n).sqrt() / (mean + 0.01)
+            })
+            .sum();
+        sum / self.color.len().max(1) as f32
+    }
+
+    /// mean of an AOV for every pixel
+    pub fn mean_aov(&self, aov: usize) -> Vec<[f32; 4]> {
+        self.aovs[aov]
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                let n = self.samples(i).max(1.);
+                [a[0] / n, a[1] / n, a[2] / n, a[3] / n]
+            })
+            .collect()
+    }
+}
+
+/// Two sets of frame buffer textures used alternately by the compute pass:
+/// one holds the accumulation of the previous passes and the other one receives the new one.
+/// Next to the color the first hit AOVs and the moments of the luminance are accumulated.
+pub struct FrameBuffer {
+    pub textures: [wgpu::Texture; 2],
+    /// aov_textures[a][i] belongs to textures[i]
+    pub aov_textures: Vec<[wgpu::Texture; 2]>,
+    /// written by the first pass only, so it is not alternated
+    pub id_texture: wgpu::Texture,
+    /// moment_textures[i] belongs to textures[i]
+    pub moment_textures: [wgpu::Texture; 2],
+    pub width: u32,
+    pub height: u32,
+    /// index of the textures holding the latest accumulation
+    current: usize,
+    /// compute_bind_groups[i] reads textures[i] and writes the other textures
+    compute_bind_groups: [wgpu::BindGroup; 2],
+    /// render_bind_groups[i] reads textures[i]
+    render_bind_groups: [wgpu::BindGroup; 2],
+}
+
+impl FrameBuffer {
+    pub fn new(
+        width: u32,
+        height: u32,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        compute_layout: &wgpu::BindGroupLayout,
+        render_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let textures = create_texture_pair(width, height, FRAME_BUFFER_FORMAT, device, queue);
+        let aov_textures = AOV_NAMES
+            .iter()
+            .map(|_| create_texture_pair(width, height, AOV_FORMAT, device, queue))
+            .collect::<Vec<_>>();
+        let id_texture = create_empty_texture(
+            device,
+            queue,
+            width,
+            height,
+            ID_FORMAT,
+            // the initial data and checkpoints are written to it
+            wgpu::TextureUsage::COPY_SRC
+                | wgpu::TextureUsage::COPY_DST
+                | wgpu::TextureUsage::SAMPLED,
+        );
+        let moment_textures = create_texture_pair(width, height, MOMENTS_FORMAT, device, queue);
+        let views = |pair: &[Texture; 2]| {
+            [
+                pair[0].create_view(&wgpu::TextureViewDescriptor::default()),
+                pair[1].create_view(&wgpu::TextureViewDescriptor::default()),
+            ]
+        };
+        let color_views = views(&textures);
+        let aov_views = aov_textures.iter().map(views).collect::<Vec<_>>();
+        let id_view = id_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let moment_views = views(&moment_textures);
+
+        let compute_bind_group = |src: usize| {
+            let mut entries = vec![
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&color_views[src]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&color_views[1 - src]),
+                },
+            ];
+            for (a, aov) in aov_views.iter().enumerate() {
+                let binding = 2 + 2 * a as u32;
+                entries.push(wgpu::BindGroupEntry {
+                    binding,
+                    resource: wgpu::BindingResource::TextureView(&aov[src]),
+                });
+                entries.push(wgpu::BindGroupEntry {
+                    binding: binding + 1,
+                    resource: wgpu::BindingResource::TextureView(&aov[1 - src]),
+                });
+            }
+            entries.push(wgpu::BindGroupEntry {
+                binding: 2 + 2 * AOV_NAMES.len() as u32,
+                resource: wgpu::BindingResource::TextureView(&id_view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: 3 + 2 * AOV_NAMES.len() as u32,
+                resource: wgpu::BindingResource::TextureView(&moment_views[src]),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: 4 + 2 * AOV_NAMES.len() as u32,
+                resource: wgpu::BindingResource::TextureView(&moment_views[1 - src]),
+            });
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("frame buffer compute bind group"),
+                layout: compute_layout,
+                entries: &entries,
+            })
+        };
+        let render_bind_group = |src: usize| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("frame buffer render bind group"),
+                layout: render_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&color_views[src]),
+                }],
+            })
+        };
+        let compute_bind_groups = [compute_bind_group(0), compute_bind_group(1)];
+        let render_bind_groups = [render_bind_group(0), render_bind_group(1)];
+
+        Self {
+            textures,
+            aov_textures,
+            id_texture,
+            moment_textures,
+            width,
+            height,
+            current: 0,
+            compute_bind_groups,
+            render_bind_groups,
+        }
+    }
+
+    /// layout of the compute bind groups: color (read, write),
+    /// every AOV (read, write), the ids (write) and the moments (read, write)
+    pub fn create_compute_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let storage_texture = |binding, access, format| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access,
+                format,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let mut entries = vec![
+            storage_texture(0, wgpu::StorageTextureAccess::ReadOnly, FRAME_BUFFER_FORMAT),
+            storage_texture(
+                1,
+                wgpu::StorageTextureAccess::WriteOnly,
+                FRAME_BUFFER_FORMAT,
+            ),
+        ];
+        for a in 0..AOV_NAMES.len() as u32 {
+            entries.push(storage_texture(
+                2 + 2 * a,
+                wgpu::StorageTextureAccess::ReadOnly,
+                AOV_FORMAT,
+            ));
+            entries.push(storage_texture(
+                3 + 2 * a,
+                wgpu::StorageTextureAccess::WriteOnly,
+                AOV_FORMAT,
+            ));
+        }
+        entries.push(storage_texture(
+            2 + 2 * AOV_NAMES.len() as u32,
+            wgpu::StorageTextureAccess::WriteOnly,
+            ID_FORMAT,
+        ));
+        entries.push(storage_texture(
+            3 + 2 * AOV_NAMES.len() as u32,
+            wgpu::StorageTextureAccess::ReadOnly,
+            MOMENTS_FORMAT,
+        ));
+        entries.push(storage_texture(
+            4 + 2 * AOV_NAMES.len() as u32,
+            wgpu::StorageTextureAccess::WriteOnly,
+            MOMENTS_FORMAT,
+        ));
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Compute Binder"),
+            entries: &entries,
+        })
+    }
+
+    /// layout of the render bind groups: color (read)
+    pub fn create_render_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Render Binder"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,                              // The location
+                visibility: wgpu::ShaderStage::FRAGMENT, // Which shader type in the pipeline this buffer is available to.
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::ReadOnly,
+                    /// Format of the texture.
+                    format: FRAME_BUFFER_FORMAT,
+                    /// Dimension of the texture view that is going to be sampled.
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            }],
+        })
+    }
+
+    pub fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        compute_layout: &wgpu::BindGroupLayout,
+        render_layout: &wgpu::BindGroupLayout,
+    ) {
+        for texture in self.textures.iter() {
+            texture.destroy();
+        }
+        for texture in self.aov_textures.iter().flatten() {
+            texture.destroy();
+        }
+        self.id_texture.destroy();
+        for texture in self.moment_textures.iter() {
+            texture.destroy();
+        }
+        *self = Self::new(width, height, device, queue, compute_layout, render_layout);
+    }
+
+    /// bind group for the compute pass, reads the current textures and writes the other ones
+    pub fn compute_bind_group(&self) -> &wgpu::BindGroup {
+        &self.compute_bind_groups[self.current]
+    }
+
+    /// bind group for the display pass, reads the current texture
+    pub fn render_bind_group(&self) -> &wgpu::BindGroup {
+        &self.render_bind_groups[self.current]
+    }
+
+    /// index of the textures holding the latest accumulation
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// copies the latest accumulation to the textures written by the next compute pass,
+    /// so pixels which are not sampled keep their value
+    pub fn encode_copy_to_next(&self, encoder: &mut wgpu::CommandEncoder) {
+        let size = wgpu::Extent3d {
+            width: self.width,
+            height: self.height,
+            depth_or_array_layers: 1,
+        };
+        let copy_texture = |texture| wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        };
+        let (current, next) = (self.current, 1 - self.current);
+        let pairs = iter::once(&self.textures)
+            .chain(self.aov_textures.iter())
+            .chain(iter::once(&self.moment_textures));
+        for pair in pairs {
+            encoder.copy_texture_to_texture(
+                copy_texture(&pair[current]),
+                copy_texture(&pair[next]),
+                size,
+            );
+        }
+    }
+
+    /// makes the textures written by the last compute pass the current ones
+    pub fn swap(&mut self) {
+        self.current = 1 - self.current;
+    }
+
+    /// copies the latest accumulation to the cpu
+    pub fn read_back(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> FrameBufferData {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("read back encoder"),
+        });
+        let read_back = self.encode_read_back(device, &mut encoder);
+        queue.submit(iter::once(encoder.finish()));
+        read_back.finish(device)
+    }
+
+    /// Records a copy of the latest accumulation, which arrives on the cpu
+    /// without blocking after the encoder was submitted
+    pub fn encode_read_back(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> ReadBack {
+        let mut copy = |texture, format| {
+            TextureCopy::encode(device, encoder, texture, self.width, self.height, format)
+        };
+        ReadBack {
+            width: self.width,
+            height: self.height,
+            color: copy(&self.textures[self.current], FRAME_BUFFER_FORMAT),
+            aovs: self
+                .aov_textures
+                .iter()
+                .map(|pair| copy(&pair[self.current], AOV_FORMAT))
+                .collect(),
+            ids: copy(&self.id_texture, ID_FORMAT),
+            moments: copy(&self.moment_textures[self.current], MOMENTS_FORMAT),
+            mappings: None,
+        }
+    }
+
+    /// replaces the latest accumulation, the inverse of `read_back`
+    pub fn upload(&self, queue: &wgpu::Queue, data: &FrameBufferData) {
+        assert!(
+            data.width == self.width && data.height == self.height,
+            "frame buffer data does not match the frame buffer size"
+        );
+        let write = |texture, bytes: &[u8], format: wgpu::TextureFormat| {
+            let texel_size = format.describe().block_size as u32;
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                bytes,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(self.width * texel_size),
+                    rows_per_image: NonZeroU32::new(self.height),
+                },
+                wgpu::Extent3d {
+                    width: self.width,
+                    height: self.height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        };
+        write(
+            &self.textures[self.current],
+            bytemuck::cast_slice(&data.color),
+            FRAME_BUFFER_FORMAT,
+        );
+        for (pair, aov) in self.aov_textures.iter().zip(data.aovs.iter()) {
+            write(&pair[self.current], bytemuck::cast_slice(aov), AOV_FORMAT);
+        }
+        write(&self.id_texture, bytemuck::cast_slice(&data.ids), ID_FORMAT);
+        write(
+            &self.moment_textures[self.current],
+            bytemuck::cast_slice(&data.moments),
+            MOMENTS_FORMAT,
+        );
+    }
+}
+
+fn create_texture_pair(
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> [Texture; 2] {
+    let create = || {
+        create_empty_texture(
+            device,
+            queue,
+            width,
+            height,
+            format,
+            wgpu::TextureUsage::COPY_SRC
+                | wgpu::TextureUsage::COPY_DST
+                | wgpu::TextureUsage::SAMPLED,
+        )
+    };
+    [create(), create()]
+}
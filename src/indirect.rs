@@ -0,0 +1,77 @@
+use wgpu::util::DeviceExt;
+
+/// number of workgroups of `group_size` threads needed for `n` threads
+pub fn div_ceil(n: u32, group_size: u32) -> u32 {
+    // unlike (n + group_size - 1) / group_size it does not overflow
+    n / group_size + (n % group_size != 0) as u32
+}
+
+/// Arguments of `dispatch_indirect` as they are stored in the buffer
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DispatchArgs {
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+}
+
+impl DispatchArgs {
+    pub const SIZE: u64 = std::mem::size_of::<Self>() as u64;
+
+    /// workgroups covering `threads` with the given workgroup size, no more and no less
+    pub fn for_threads(threads: [u32; 3], group_size: [u32; 3]) -> Self {
+        Self {
+            x: div_ceil(threads[0], group_size[0]),
+            y: div_ceil(threads[1], group_size[1]),
+            z: div_ceil(threads[2], group_size[2]),
+        }
+    }
+}
+
+/// Arguments of `draw_indirect` as they are stored in the buffer
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DrawArgs {
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    pub base_vertex: u32,
+    pub base_instance: u32,
+}
+
+/// Buffer of indirect arguments, compute passes can write it to size the dispatches
+/// and draws which follow them
+pub fn create_buffer<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    label: &str,
+    args: &[T],
+) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: bytemuck::cast_slice(args),
+        usage: wgpu::BufferUsage::INDIRECT
+            | wgpu::BufferUsage::STORAGE
+            | wgpu::BufferUsage::COPY_DST,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn div_ceil_covers_partial_groups() {
+        assert_eq!(div_ceil(33, 32), 2);
+        assert_eq!(div_ceil(32, 32), 1);
+        assert_eq!(div_ceil(1, 32), 1);
+        assert_eq!(div_ceil(0, 32), 0);
+        assert_eq!(div_ceil(u32::MAX, 256), (u32::MAX >> 8) + 1);
+    }
+
+    #[test]
+    fn dispatch_covers_every_thread() {
+        let args = DispatchArgs::for_threads([33, 16, 1], [32, 16, 1]);
+        assert_eq!([args.x, args.y, args.z], [2, 1, 1]);
+        let args = DispatchArgs::for_threads([0, 0, 0], [32, 16, 1]);
+        assert_eq!([args.x, args.y, args.z], [0, 0, 0]);
+    }
+}
@@ -0,0 +1,236 @@
+// Radiance of the paths, debug views and the accumulation in the frame buffer
+
+#include "sampling.wgsl"
+#include "intersect.wgsl"
+
+
+fn lightColor(init_ray:Ray) -> vec3<f32> {
+
+    var ray:Ray = init_ray;
+
+    var specularBounce : bool = true;
+
+    let light = light_sphere();
+    let lightMaterial = scene.materials[light.materialIdx];
+
+    var color : vec3<f32> = vec3<f32>(0.0,0.0,0.0);
+    var mask : vec3<f32> = vec3<f32>(1.0,1.0,1.0);
+    for (var hits:u32 = 0u; hits <= uniforms.max_depth; hits=hits+1u) {
+        start_bounce(hits);
+        if (!hitScene(ray)){
+            // TODO env map
+            break;
+        }
+        
+        let material = scene.materials[intersec.materialIdx];
+
+        if (hits == 0u) {
+            // ids start at 1 so that 0 means no hit
+            let forward = normalize((uniforms.u_view_proj * vec4<f32>(0.0, 0.0, 1.0, 0.0)).xyz);
+            first_hit = FirstHit(
+                material.color.rgb,
+                intersec.normal,
+                intersec.pos,
+                intersec.lambda * dot(ray.dir, forward),
+                intersec.materialIdx + 1u,
+                intersec.objectIdx + 1u,
+                intersec.uv,
+                faceForward(intersec.normal, ray.dir, intersec.normal),
+            );
+            // only the traversal steps need the whole path
+            if (uniforms.debug_view != DEBUG_OFF && uniforms.debug_view != DEBUG_TRAVERSAL_STEPS) {
+                return color;
+            }
+        }
+
+        let emissiveness = material.color.a;
+
+        if (emissiveness > 0.0) {
+            // the sphere light is sampled by next event estimation, emissive faces only
+            // when they are hit
+            if (specularBounce || intersec.objectIdx >= num_spheres){
+                color = color + (mask * material.color.rgb * emissiveness);
+            }
+            return color;
+        }
+
+        specularBounce = false;
+
+        let r2 = random();
+        let d = jitter(intersec.normal, 2. * PI * random(), sqrt(r2), sqrt(1. - r2));
+
+
+        // calc incomming light
+        mask = mask * material.color.rgb;
+        color =  color + (mask * emissiveness);
+
+
+        ray.orig = intersec.pos;
+        ray.dir =  d;
+
+        let lightRay = (light.center) - intersec.pos;
+        let lightDir = normalize(lightRay);
+
+        // if (dot(-lightDir, c * intersec.normal) >= 0.0) {
+        //     continue;
+        // }
+        // TODO add max distance param to hitScene inorder to make light check
+        // easier
+        let o_normal = intersec.normal;
+        let hitAny = hitScene(Ray(intersec.pos, lightDir));
+
+		// check if the light was hit
+        if (hitAny && intersec.objectIdx < num_spheres){
+			let sphere_radius = light.radius;
+
+            let cos_a_max =
+                sqrt(1.0 - clamp(sphere_radius*sphere_radius / (intersec.lambda * intersec.lambda), 0.0, 1.0));
+            let weight = 2.0 * (1.0 - cos_a_max);
+            // calc next event estimation
+            color = color + (mask * lightMaterial.color.rgb * lightMaterial.color.a) *
+                     (weight * dot(lightDir, o_normal));
+        }
+
+        // russian roulette: terminate paths with low throughput
+        if (uniforms.rr_depth > 0u && hits >= uniforms.rr_depth) {
+            let p = clamp(max(mask.r, max(mask.g, mask.b)), 0.05, 1.0);
+            if (random() > p) {
+                break;
+            }
+            mask = mask / p;
+        }
+    }
+    return color;
+}
+
+// turbo color map by Anton Mikhailov, polynomial approximation, returns linear rgb
+fn heat_map(x: f32) -> vec3<f32> {
+    let r4 = vec4<f32>(0.13572138, 4.61539260, -42.66032258, 132.13108234);
+    let g4 = vec4<f32>(0.09140261, 2.19418839, 4.84296658, -14.18503333);
+    let b4 = vec4<f32>(0.10667330, 12.64194608, -60.58204836, 110.36276771);
+    let r2 = vec2<f32>(-152.94239396, 59.28637943);
+    let g2 = vec2<f32>(4.27729857, 2.82956604);
+    let b2 = vec2<f32>(-89.90310912, 27.34824973);
+    let t = clamp(x, 0.0, 1.0);
+    let v4 = vec4<f32>(1.0, t, t * t, t * t * t);
+    let v2 = v4.zw * v4.z;
+    let srgb = vec3<f32>(
+        dot(v4, r4) + dot(v2, r2),
+        dot(v4, g4) + dot(v2, g2),
+        dot(v4, b4) + dot(v2, b2),
+    );
+    // the display pass encodes to sRGB again
+    return pow(clamp(srgb, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(2.2));
+}
+
+fn is_heat_map(view: u32) -> bool {
+    return view == DEBUG_TRAVERSAL_STEPS || view == DEBUG_SAMPLE_COUNT;
+}
+
+// the legend is a vertical bar at the right border with ticks at every quarter
+fn in_legend(pix: vec2<u32>, size: vec2<u32>) -> bool {
+    return is_heat_map(uniforms.debug_view) && size.x > 48u && size.y > 48u
+        && pix.x >= size.x - 32u && pix.x < size.x - 16u
+        && pix.y >= 16u && pix.y < size.y - 16u;
+}
+
+fn legend_color(pix: vec2<u32>, size: vec2<u32>) -> vec3<f32> {
+    let height = f32(size.y - 32u);
+    let x = 1.0 - (f32(pix.y - 16u) + 0.5) / height;
+    let tick = fract(x * 4.0) < 4.0 / height;
+    if (tick && pix.x < size.x - 26u) {
+        return vec3<f32>(0.0, 0.0, 0.0);
+    }
+    return heat_map(x);
+}
+
+fn hash_color(id: u32) -> vec3<f32> {
+    let h = hash(id);
+    return vec3<f32>(f32(h & 255u), f32((h >> 8u) & 255u), f32((h >> 16u) & 255u)) / 255.0;
+}
+
+// color of a sample in the debug views
+fn debug_color(view: u32) -> vec3<f32> {
+    if (view == DEBUG_TRAVERSAL_STEPS) {
+        // every bounce traces a path and a shadow ray
+        let max_steps = (num_spheres + uniforms.num_faces) * 2u * (uniforms.max_depth + 1u);
+        return heat_map(f32(traversal_steps) / f32(max_steps));
+    }
+    if (first_hit.objectIdx == 0u) {
+        return vec3<f32>(0.0, 0.0, 0.0);
+    }
+    if (view == DEBUG_SHADING_NORMAL) {
+        return (first_hit.shading_normal + 1.0) / 2.0;
+    }
+    if (view == DEBUG_GEOMETRIC_NORMAL) {
+        return (first_hit.normal + 1.0) / 2.0;
+    }
+    if (view == DEBUG_ALBEDO) {
+        return first_hit.albedo;
+    }
+    if (view == DEBUG_DEPTH) {
+        return vec3<f32>(DEBUG_DEPTH_SCALE / (first_hit.depth + DEBUG_DEPTH_SCALE));
+    }
+    if (view == DEBUG_BARYCENTRICS) {
+        return vec3<f32>(1.0 - first_hit.uv.x - first_hit.uv.y, first_hit.uv.x, first_hit.uv.y);
+    }
+    if (view == DEBUG_TRIANGLE_ID) {
+        return hash_color(first_hit.objectIdx);
+    }
+    return vec3<f32>(0.0, 0.0, 0.0);
+}
+
+// adds the sample of a pixel to the frame buffer, first_hit and traversal_steps
+// have to hold the ones of the sample
+fn accumulate(pix: vec2<u32>, image_pix: vec2<u32>, color: vec3<f32>, filter_weight: f32) {
+    let image_size = uniforms.image_size;
+    var colorOut: vec3<f32> = color;
+
+    // clamp the sample to remove fireflies
+    let maxComponent = max(colorOut.r, max(colorOut.g, colorOut.b));
+    if (uniforms.radiance_clamp > 0.0 && maxComponent > uniforms.radiance_clamp) {
+        colorOut = colorOut * (uniforms.radiance_clamp / maxComponent);
+    }
+    if (uniforms.debug_view != DEBUG_OFF) {
+        colorOut = debug_color(uniforms.debug_view);
+    }
+    if (in_legend(image_pix, image_size)) {
+        colorOut = legend_color(image_pix, image_size);
+    }
+    colorOut = colorOut * filter_weight;
+
+    // add to the sum of the previous samples, alpha counts the samples
+    var accumulated: vec4<f32> = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    if (uniforms.pass > 0u) {
+        accumulated = textureLoad(framebuffer_src, vec2<i32>(pix));
+    }
+    accumulated = accumulated + vec4<f32>(colorOut, 1.0);
+    if (uniforms.debug_view == DEBUG_SAMPLE_COUNT && !in_legend(image_pix, image_size)) {
+        // the mean of the stored color is the heat of the sample count
+        let heat = log2(accumulated.a) / log2(DEBUG_MAX_SAMPLES);
+        accumulated = vec4<f32>(heat_map(heat) * accumulated.a, accumulated.a);
+    }
+
+    textureStore(framebuffer_dst, vec2<i32>(pix), accumulated);
+
+    // the AOVs are accumulated like the color so they are filtered the same way
+    let coords = vec2<i32>(pix);
+    var albedo: vec4<f32> = vec4<f32>(first_hit.albedo, 0.0) * filter_weight;
+    var normal: vec4<f32> = vec4<f32>(first_hit.normal, 0.0) * filter_weight;
+    var position: vec4<f32> = vec4<f32>(first_hit.pos, first_hit.depth) * filter_weight;
+    let l = dot(colorOut, vec3<f32>(0.2126, 0.7152, 0.0722));
+    var moments: f32 = l * l;
+    if (uniforms.pass > 0u) {
+        albedo = albedo + textureLoad(albedo_src, coords);
+        normal = normal + textureLoad(normal_src, coords);
+        position = position + textureLoad(position_src, coords);
+        moments = moments + textureLoad(moments_src, coords).x;
+    } else {
+        // ids can not be averaged, the first sample decides
+        textureStore(ids, coords, vec4<u32>(first_hit.materialIdx, first_hit.objectIdx, 0u, 0u));
+    }
+    textureStore(albedo_dst, coords, albedo);
+    textureStore(normal_dst, coords, normal);
+    textureStore(position_dst, coords, position);
+    textureStore(moments_dst, coords, vec4<f32>(moments, 0.0, 0.0, 0.0));
+}
@@ -0,0 +1,158 @@
+// Camera rays and the intersection of the scene
+
+#include "common.wgsl"
+
+fn cast_ray_from_camera(c: Camera, uv:vec2<f32>) -> Ray{
+    let ray =
+        normalize(vec3<f32>((uv.x - 0.5) * c.ratio, -(uv.y - 0.5), c.focal_length));
+    let center = (uniforms.u_view_proj * vec4<f32>(0.0, 0.0, 0.0, 1.0)).xyz;
+    return Ray(center,
+               (uniforms.u_view_proj * vec4<f32>(ray, 1.0)).xyz - center);
+}
+
+var intersec: Intersection = Intersection(
+    vec3<f32>(0.0,0.0,0.0),
+    vec3<f32>(0.0,0.0,0.0),
+    0u,
+    0u,
+    vec2<f32>(0.0, 0.0),
+    0.0,
+);
+
+// number of primitive intersection tests of the current sample
+var traversal_steps: u32 = 0u;
+
+// attributes of the first hit written to the AOVs
+struct FirstHit {
+    albedo: vec3<f32>;
+    normal: vec3<f32>;
+    pos: vec3<f32>;
+    depth: f32;
+    materialIdx: u32;
+    objectIdx: u32;
+    uv: vec2<f32>;
+    // normal facing the camera
+    shading_normal: vec3<f32>;
+};
+
+var first_hit: FirstHit = FirstHit(
+    vec3<f32>(0.0,0.0,0.0),
+    vec3<f32>(0.0,0.0,0.0),
+    vec3<f32>(0.0,0.0,0.0),
+    0.0,
+    0u,
+    0u,
+    vec2<f32>(0.0, 0.0),
+    vec3<f32>(0.0, 0.0, 0.0),
+);
+
+
+fn sphere_intersection(s:Sphere, index:u32, ray:Ray) -> bool{
+    let d = ray.orig - s.center;
+    let vd = dot(ray.dir, d);
+
+    let dd = dot(d, d); // length squared
+
+    let r = s.radius;
+
+    let a = vd * vd - dd + r * r;
+    if (a <= 0.) {
+        return false;
+    }
+    let ss = sqrt(a);
+    let l1 = f32(-vd + ss);
+    let l2 = f32(-vd - ss);
+
+    if (l2 > uniforms.min_distance || l1 > uniforms.min_distance) {
+        let l = min(l1, l2);
+        if (l > intersec.lambda) {
+            return false;
+        }
+
+        intersec.lambda = l;
+        let intersect = ray.orig + l * ray.dir;
+        let normal = (intersect - s.center) / s.radius;
+
+        intersec.pos = intersect;
+        intersec.normal = normal;
+        intersec.materialIdx = s.materialIdx;
+        intersec.objectIdx = index;
+        intersec.uv = vec2<f32>(0.0, 0.0);
+        return true;
+    }
+    return false;
+}
+
+var hit_index:u32 = 0u;
+
+fn triangle_intersection(ray:Ray, t: Triangle) -> bool {
+    let edge1 = t.p2 - t.p1;
+    let edge2 = t.p3 - t.p1;
+    let h = cross(ray.dir, edge2);
+    let a = dot(edge1, h);
+    if (a > -uniforms.min_distance && a < uniforms.min_distance) {
+        return false;
+    }
+    let f = 1.0 / a;
+    let s = ray.orig - t.p1;
+    let u = f * dot(s, h);
+    if (u < 0.0 || u > 1.0) {
+        return false;
+    }
+    let q = cross(s, edge1);
+    let v = f * dot(ray.dir, q);
+    if (v < 0.0 || u + v > 1.0) {
+        return false;
+    }
+    let lambda = f * dot(edge2, q);
+
+    if (lambda > uniforms.min_distance && lambda < intersec.lambda) {
+        let normal =
+            normalize(cross(t.p1 - t.p2,
+                            t.p3 - t.p1));
+
+        intersec.pos = ray.orig + ray.dir * lambda;
+        intersec.normal = normal;
+        intersec.materialIdx = t.materialIdx;
+        intersec.uv = vec2<f32>(u, v);
+
+        intersec.lambda = lambda;
+        return true;
+    }
+
+    return false;
+}
+
+
+fn hitScene(ray:Ray) -> bool {
+    var anyHit: bool = false;
+
+    intersec.lambda = 1.0 / 0.0; // aka. infinity
+    traversal_steps = traversal_steps + num_spheres + uniforms.num_faces;
+    anyHit = sphere_intersection(light_sphere(), 0u, ray);
+
+    for (var i:u32 = 0u; i < uniforms.num_faces; i = i+1u) {
+		let face = faces.data[i];
+		let p1 = face.x;
+		let p2 = face.y;
+		let p3 = face.z;
+
+		let v1 = vertices.data[p1];
+		let v2 = vertices.data[p3];
+		let v3 = vertices.data[p2];
+
+
+		let tri = Triangle(
+			vec3<f32>(v1.x,v1.y,v1.z),
+			vec3<f32>(v2.x,v2.y,v2.z),
+			vec3<f32>(v3.x,v3.y,v3.z),
+			face.w
+		);
+        if (triangle_intersection(ray, tri)) {
+            intersec.objectIdx = num_spheres + i;
+            anyHit = true;
+        }
+    }
+    return anyHit;
+}
+
@@ -0,0 +1,272 @@
+use std::time::{Duration, Instant};
+
+use crate::settings::JobSettings;
+
+/// passes between two measurements of the error, every measurement reads back the frame buffer
+const ERROR_INTERVAL: u32 = 16;
+/// time between two progress reports
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Snapshot of the progress of a render job
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub passes: u32,
+    pub elapsed: Duration,
+    /// samples of all pixels per second
+    pub samples_per_second: f32,
+    /// latest measured mean relative error
+    pub error: Option<f32>,
+    /// estimated time until the first stop condition is reached
+    pub eta: Option<Duration>,
+    /// a stop condition is reached
+    pub done: bool,
+}
+
+impl std::fmt::Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} passes in {:.1}s, {:.2} Msamples/s",
+            self.passes,
+            self.elapsed.as_secs_f32(),
+            self.samples_per_second / 1e6
+        )?;
+        if let Some(error) = self.error {
+            write!(f, ", error {:.4}", error)?;
+        }
+        if let Some(eta) = self.eta {
+            write!(f, ", eta {:.1}s", eta.as_secs_f32())?;
+        }
+        Ok(())
+    }
+}
+
+/// Decides when the accumulation is finished and reports its progress.
+/// The job has to be restarted whenever the accumulation is reset
+pub struct RenderJob {
+    settings: JobSettings,
+    /// pixels sampled by every pass
+    pixels: u32,
+    start: Instant,
+    last_report: Instant,
+    passes: u32,
+    error: Option<f32>,
+    done: bool,
+}
+
+impl RenderJob {
+    pub fn new(settings: JobSettings, pixels: u32) -> Self {
+        let now = Instant::now();
+        Self {
+            settings,
+            pixels,
+            start: now,
+            last_report: now,
+            passes: 0,
+            error: None,
+            done: false,
+        }
+    }
+
+    /// starts over with an empty accumulation
+    pub fn restart(&mut self, pixels: u32) {
+        *self = Self::new(self.settings, pixels);
+    }
+
+    /// true once a stop condition is reached, no more passes should be rendered
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// true if the error should be measured after the passes which are about to be finished,
+    /// `passes` is the number of passes once they are
+    pub fn needs_error(&self, passes: u32) -> bool {
+        self.settings.max_error.is_some() && passes / ERROR_INTERVAL > self.passes / ERROR_INTERVAL
+    }
+
+    /// passes left until the sample count of the job is reached
+    pub fn remaining_passes(&self) -> Option<u32> {
+        self.settings.spp.map(|spp| spp.saturating_sub(self.passes))
+    }
+
+    /// Records finished passes and checks the stop conditions.
+    /// `error` is the mean relative error if it was measured, it may lag a few passes behind.
+    /// Returns the progress if a report is due, every REPORT_INTERVAL and when the job is done.
+    /// Jobs without stop conditions are not reported
+    pub fn finish_pass(&mut self, passes: u32, error: Option<f32>) -> Option<Progress> {
+        self.passes = passes;
+        if error.is_some() {
+            self.error = error;
+        }
+
+        let settings = &self.settings;
+        self.done = settings.spp.map_or(false, |spp| passes >= spp)
+            || settings
+                .time_limit
+                .map_or(false, |limit| self.start.elapsed() >= limit)
+            || settings
+                .max_error
+                .zip(error)
+                .map_or(false, |(max, error)| error <= max);
+
+        if !settings.is_bounded() {
+            return None;
+        }
+        if self.done || self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.last_report = Instant::now();
+            return Some(self.progress());
+        }
+        None
+    }
+
+    pub fn progress(&self) -> Progress {
+        let elapsed = self.start.elapsed();
+        let passes_per_second = self.passes as f32 / elapsed.as_secs_f32().max(1e-6);
+        let pass_time = |passes: f32| Duration::from_secs_f32(passes / passes_per_second);
+
+        let eta = if self.done || self.passes == 0 {
+            None
+        } else {
+            let spp = self.settings.spp.map(|spp| {
+                let remaining = spp.saturating_sub(self.passes);
+                pass_time(remaining as f32)
+            });
+            let time = self
+                .settings
+                .time_limit
+                .map(|limit| limit.checked_sub(elapsed).unwrap_or_default());
+            // the error falls with the square root of the samples
+            let error = self.settings.max_error.zip(self.error).map(|(max, error)| {
+                let needed = self.passes as f32 * (error / max).powi(2);
+                pass_time((needed - self.passes as f32).max(0.))
+            });
+            [spp, time, error].iter().flatten().min().copied()
+        };
+
+        Progress {
+            passes: self.passes,
+            elapsed,
+            samples_per_second: passes_per_second * self.pixels as f32,
+            error: self.error,
+            eta,
+            done: self.done,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(spp: Option<u32>, time_limit: Option<u64>, max_error: Option<f32>) -> RenderJob {
+        let settings = JobSettings {
+            spp,
+            time_limit: time_limit.map(Duration::from_secs),
+            max_error,
+        };
+        RenderJob::new(settings, 100)
+    }
+
+    /// pretends the job was started `seconds` ago
+    fn started(mut job: RenderJob, seconds: u64) -> RenderJob {
+        job.start = Instant::now() - Duration::from_secs(seconds);
+        job
+    }
+
+    fn assert_eta(progress: Progress, seconds: f32) {
+        let eta = progress.eta.unwrap().as_secs_f32();
+        assert!(
+            (eta - seconds).abs() < 0.5,
+            "eta {} instead of {}",
+            eta,
+            seconds
+        );
+    }
+
+    #[test]
+    fn stops_at_spp() {
+        let mut job = job(Some(4), None, None);
+        assert_eq!(job.remaining_passes(), Some(4));
+        job.finish_pass(3, None);
+        assert!(!job.is_done());
+        assert_eq!(job.remaining_passes(), Some(1));
+        let progress = job.finish_pass(4, None).unwrap();
+        assert!(job.is_done() && progress.done);
+        assert_eq!(progress.passes, 4);
+        assert_eq!(job.remaining_passes(), Some(0));
+    }
+
+    #[test]
+    fn stops_at_time_limit() {
+        let mut job = job(None, Some(60), None);
+        job.finish_pass(1, None);
+        assert!(!job.is_done());
+        let mut job = started(job, 61);
+        assert!(job.finish_pass(2, None).unwrap().done);
+    }
+
+    #[test]
+    fn stops_at_max_error() {
+        let mut job = job(None, None, Some(0.1));
+        job.finish_pass(16, Some(0.2));
+        assert!(!job.is_done());
+        // the last measurement is kept but only a new one can stop the job
+        job.finish_pass(20, None);
+        assert!(!job.is_done());
+        assert_eq!(job.progress().error, Some(0.2));
+        let progress = job.finish_pass(32, Some(0.05)).unwrap();
+        assert!(progress.done);
+        assert_eq!(progress.error, Some(0.05));
+    }
+
+    #[test]
+    fn measures_error_every_interval() {
+        let mut with_error = job(None, None, Some(0.1));
+        assert!(!with_error.needs_error(ERROR_INTERVAL - 1));
+        assert!(with_error.needs_error(ERROR_INTERVAL));
+        assert!(with_error.needs_error(ERROR_INTERVAL + 3));
+        with_error.finish_pass(ERROR_INTERVAL + 1, None);
+        assert!(!with_error.needs_error(2 * ERROR_INTERVAL - 1));
+        assert!(with_error.needs_error(2 * ERROR_INTERVAL));
+        let without_error = job(Some(100), None, None);
+        assert!(!without_error.needs_error(ERROR_INTERVAL));
+    }
+
+    #[test]
+    fn reports_only_bounded_jobs() {
+        let mut unbounded = started(job(None, None, None), 10);
+        assert!(unbounded.finish_pass(1, None).is_none());
+        assert!(!unbounded.is_done());
+        let mut bounded = job(Some(10), None, None);
+        assert!(bounded.finish_pass(1, None).is_none());
+        bounded.last_report -= REPORT_INTERVAL;
+        assert!(bounded.finish_pass(2, None).is_some());
+    }
+
+    #[test]
+    fn estimates_remaining_time() {
+        // 50 passes in 10s are 5 passes per second
+        let mut spp = started(job(Some(100), None, None), 10);
+        spp.finish_pass(50, None);
+        assert_eta(spp.progress(), 10.);
+        assert!((spp.progress().samples_per_second - 500.).abs() < 20.);
+
+        let mut time = started(job(None, Some(60), None), 10);
+        time.finish_pass(50, None);
+        assert_eta(time.progress(), 50.);
+
+        // halving the error takes four times the samples
+        let mut error = started(job(None, None, Some(0.1)), 10);
+        error.finish_pass(50, Some(0.2));
+        assert_eta(error.progress(), 30.);
+
+        let mut all = started(job(Some(100), Some(60), Some(0.1)), 10);
+        all.finish_pass(50, Some(0.2));
+        assert_eta(all.progress(), 10.);
+
+        let mut done = started(job(Some(50), None, None), 10);
+        done.finish_pass(50, None);
+        assert_eq!(done.progress().eta, None);
+        assert_eq!(job(Some(50), None, None).progress().eta, None);
+    }
+}
@@ -1,8 +1,9 @@
 use core::f32;
-use std::iter;
 
-use cgmath::{prelude::*, Vector2};
-use std::borrow::Cow;
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use wgpu::util::DeviceExt;
 use winit::{
@@ -11,54 +12,45 @@ use winit::{
     window::Window,
 };
 
-mod camera;
-mod cornell_box;
-mod lib;
-mod pipeline;
-
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct Uniforms {
-    view_proj: [[f32; 4]; 4],
-    time: f32,
-    pass: u32,
-    num_faces: u32,
-}
+use rey::{
+    benchmark, camera, capture, cornell_box, denoise, display,
+    frame_buffer::ReadBack,
+    indirect, job, offline, pacing, pipeline, renderer,
+    scene::SceneFile,
+    settings,
+    shader::{self, Defines, ShaderLibrary},
+    submission::SubmissionQueue,
+    watch, Renderer, RendererSettings, Scene,
+};
 
-impl Uniforms {
-    fn new() -> Self {
-        Self {
-            view_proj: cgmath::Matrix4::identity().into(),
-            time: 0.0,
-            pass: 0,
-            num_faces: 0,
-        }
-    }
+mod controller;
 
-    fn increment_time(&mut self, dt: f32) {
-        self.time += dt;
-    }
+/// frames submitted ahead of the GPU
+const FRAMES_IN_FLIGHT: usize = 2;
+/// time the camera has to rest before the full resolution is rendered again
+const MOTION_REST_TIME: Duration = Duration::from_millis(200);
 
-    fn increment_pass(&mut self) {
-        self.pass += 1;
-    }
-
-    fn reset_pass(&mut self) {
-        self.pass = 0;
-    }
+/// Development mode which recompiles the shaders when their files change
+struct ShaderReload {
+    watcher: watch::FileWatcher,
+    errors: pipeline::ErrorCapture,
+}
 
-    // UPDATED!
-    fn update_view_proj(&mut self, camera: &camera::Camera, _projection: &camera::Projection) {
-        self.view_proj = (camera.calc_matrix()).into() // TODO add perspective (ratio usw.)
-    }
+/// Screenshot requested with F12, saved once the frame buffer arrived on the cpu
+struct PendingScreenshot {
+    read_back: ReadBack,
+    passes: u32,
+    camera: camera::Camera,
+    settings: settings::Settings,
 }
+
 struct State {
     camera: camera::Camera,
     projection: camera::Projection,
-    camera_controller: camera::CameraController,
-    uniforms: Uniforms,
-    uniform_buffer: wgpu::Buffer,
-    uniform_bind_group: wgpu::BindGroup,
+    camera_controller: controller::CameraController,
+    settings: settings::Settings,
+    display_buffer: wgpu::Buffer,
+    display_bind_group: wgpu::BindGroup,
 
     surface: wgpu::Surface,
     device: wgpu::Device,
@@ -66,24 +58,44 @@ struct State {
     sc_desc: wgpu::SwapChainDescriptor,
     swap_chain: wgpu::SwapChain,
     size: winit::dpi::PhysicalSize<u32>,
+    render_pipeline_layout: wgpu::PipelineLayout,
     render_pipeline: wgpu::RenderPipeline,
-    compute_pipeline: wgpu::ComputePipeline,
-
-    frame_buffer: lib::FrameBuffer,
-
-    render_bind_layout: wgpu::BindGroupLayout,
-    render_bind_group: wgpu::BindGroup,
-
-    framebuffer_bind_group_layout: wgpu::BindGroupLayout,
-    framebuffer_bind_group: wgpu::BindGroup,
-
-    vertex_bind_group: wgpu::BindGroup,
+    /// draw arguments of the display pass, a full screen quad
+    display_args: wgpu::Buffer,
+
+    renderer: Renderer,
+    denoiser: denoise::Denoiser,
+    job: job::RenderJob,
+    /// compute passes per frame while the camera rests
+    pass_budget: pacing::PassBudget,
+    submissions: SubmissionQueue,
+    /// frame buffer copy for the error of the job, it lags a few passes behind
+    pending_error: Option<ReadBack>,
+    pending_screenshot: Option<PendingScreenshot>,
+    shader_reload: Option<ShaderReload>,
+    /// the scene file and its meshes, the scene is reloaded when they change
+    scene_watcher: Option<watch::FileWatcher>,
 
     mouse_pressed: bool,
+    /// the camera moved within the last MOTION_REST_TIME, the resolution is lowered while
+    /// it moves
+    moving: bool,
+    last_motion: Option<Instant>,
+    /// position where the drag of a region of interest started, see `normalize`
+    roi_start: Option<(f64, f64)>,
+    /// corners of the region of interest, see `normalize`.
+    /// The renderer forgets it when the frame buffer is resized, so it is applied again
+    roi: Option<((f64, f64), (f64, f64))>,
+    /// region of interest while it is dragged
+    roi_preview: Option<renderer::Region>,
 }
 
 impl State {
-    async fn new(window: &Window) -> Self {
+    async fn new(
+        window: &Window,
+        settings: settings::Settings,
+        scene: SceneFile,
+    ) -> Result<Self, String> {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -96,13 +108,15 @@ impl State {
             })
             .await
             .unwrap();
+        let limits =
+            renderer::required_limits(&adapter.limits(), settings.integrator.architecture)?;
 
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
                     features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
+                    limits,
                 },
                 None, // Trace path
             )
@@ -118,30 +132,47 @@ impl State {
         };
         let swap_chain = device.create_swap_chain(&surface, &sc_desc);
 
-        let camera = camera::Camera::new(
-            (250.0, 350.0, -260.0),
-            cgmath::Deg(0.0),
-            cgmath::Deg(0.0),
-            cgmath::Deg(0.0),
-        );
+        let camera = scene.camera.unwrap_or_else(cornell_box::camera);
         let projection = camera::Projection::new(sc_desc.width, sc_desc.height, cgmath::Deg(45.0));
-        let camera_controller = camera::CameraController::new(400.0, 0.4);
+        let camera_controller = controller::CameraController::new(400.0, 0.4);
+
+        let (render_width, render_height) = settings.resolution.render_size(
+            (size.width, size.height),
+            false,
+            device.limits().max_texture_dimension_2d,
+        );
+        let mut renderer = Renderer::new(
+            &device,
+            &queue,
+            &scene.scene,
+            &RendererSettings {
+                width: render_width,
+                height: render_height,
+                integrator: settings.integrator,
+            },
+        );
+        renderer.set_camera(&camera);
+        let denoiser = denoise::Denoiser::new(
+            &device,
+            &queue,
+            &renderer.frame_buffer,
+            renderer.render_bind_layout(),
+        );
 
-        let mut uniforms = Uniforms::new();
-        uniforms.update_view_proj(&camera, &projection);
-        uniforms.num_faces = cornell_box::FACES.len() as u32;
+        let job = job::RenderJob::new(settings.job, render_width * render_height);
 
-        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Uniform Buffer"),
-            contents: bytemuck::cast_slice(&[uniforms]),
+        let display_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Display Buffer"),
+            contents: bytemuck::cast_slice(&[display::DisplayUniforms::new(&settings.display)
+                .with_viewport(size.width, size.height)]),
             usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
         });
 
-        let uniform_bind_group_layout =
+        let display_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStage::COMPUTE,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -149,192 +180,74 @@ impl State {
                     },
                     count: None,
                 }],
-                label: Some("uniform_bind_layout"),
+                label: Some("display_bind_layout"),
             });
 
-        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &uniform_bind_group_layout,
+        let display_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &display_bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
-                resource: uniform_buffer.as_entire_binding(),
+                resource: display_buffer.as_entire_binding(),
             }],
-            label: Some("uniform_bind_group"),
+            label: Some("display_bind_group"),
         });
 
-        let render_bind_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Render Binder"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,                              // The location
-                    visibility: wgpu::ShaderStage::FRAGMENT, // Which shader type in the pipeline this buffer is available to.
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::ReadOnly,
-                        /// Format of the texture.
-                        format: wgpu::TextureFormat::Rgba16Float,
-                        /// Dimension of the texture view that is going to be sampled.
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                    },
-                    count: None,
-                }],
-            });
-
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&render_bind_layout],
+                bind_group_layouts: &[renderer.render_bind_layout(), &display_bind_group_layout],
                 push_constant_ranges: &[],
             });
 
-        let flags = wgpu::ShaderFlags::VALIDATION;
-
-        let render_pipeline = pipeline::create_render_pipeline(
+        let render_pipeline = create_display_pipeline(
             &device,
             &render_pipeline_layout,
             sc_desc.format,
-            wgpu::ShaderModuleDescriptor {
-                label: Some("display_shader"),
-                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("display.wgsl"))),
-                flags,
-            },
+            &ShaderLibrary::builtin(),
         );
 
-        let framebuffer_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Compute Binder"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStage::COMPUTE,
-                        ty: wgpu::BindingType::StorageTexture {
-                            access: wgpu::StorageTextureAccess::ReadOnly,
-                            /// Format of the texture.
-                            format: wgpu::TextureFormat::Rgba16Float,
-                            /// Dimension of the texture view that is going to be sampled.
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStage::COMPUTE,
-                        ty: wgpu::BindingType::StorageTexture {
-                            access: wgpu::StorageTextureAccess::WriteOnly,
-                            /// Format of the texture.
-                            format: wgpu::TextureFormat::Rgba16Float,
-                            /// Dimension of the texture view that is going to be sampled.
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                ],
-            });
-
-        let frame_buffer = lib::FrameBuffer::new(size.width, size.height, &device, &queue);
-
-        let (fb_src_view, fb_dst_view) = frame_buffer.create_views();
-
-        // Instantiates the bind group, once again specifying the binding of buffers.
-        let framebuffer_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &framebuffer_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&fb_src_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&fb_dst_view),
-                },
-            ],
-        });
-
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&cornell_box::VERTICES),
-            usage: wgpu::BufferUsage::STORAGE,
-        });
-
-        let face_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("face buffer"),
-            contents: bytemuck::cast_slice(&cornell_box::FACES),
-            usage: wgpu::BufferUsage::STORAGE,
-        });
-
-        let vertex_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("vertex bind layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStage::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStage::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                ],
-            });
+        let scene_watcher = if scene.files.is_empty() {
+            None
+        } else {
+            Some(watch::FileWatcher::new(scene.files))
+        };
 
-        let vertex_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("vertex bind group"),
-            layout: &vertex_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: vertex_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: face_buffer.as_entire_binding(),
-                },
-            ],
-        });
+        let shader_reload = if settings.watch_shaders {
+            let dir = Path::new(pipeline::SHADER_DIR);
+            // the adaptive sampler and the denoiser keep their builtin shaders
+            let files = shader::file_names()
+                .filter(|f| !["adaptive.wgsl", "denoise.wgsl"].contains(f))
+                .map(|f| dir.join(f))
+                .collect();
+            println!("watching the shaders in {}", dir.display());
+            Some(ShaderReload {
+                watcher: watch::FileWatcher::new(files),
+                errors: pipeline::ErrorCapture::install(&device),
+            })
+        } else {
+            None
+        };
 
-        let compute_pipeline = pipeline::create_compute_pipeline(
+        let display_args = indirect::create_buffer(
             &device,
-            &[
-                &framebuffer_bind_group_layout,
-                &uniform_bind_group_layout,
-                &vertex_bind_group_layout,
-            ],
-            wgpu::ShaderModuleDescriptor {
-                label: Some("display_shader"),
-                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("compute.wgsl"))),
-                flags,
-            },
-            Some("ComputePipeline"),
+            "display draw args",
+            &[indirect::DrawArgs {
+                vertex_count: 6,
+                instance_count: 1,
+                ..Default::default()
+            }],
         );
 
-        // Instantiates the bind group, once again specifying the binding of buffers.
-        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &render_bind_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&fb_dst_view),
-            }],
-        });
+        let pass_budget = pacing::PassBudget::new(settings.pacing.target_frame_time);
+        let submissions = SubmissionQueue::new(&device, FRAMES_IN_FLIGHT);
 
-        Self {
+        Ok(Self {
             camera,
             projection,
             camera_controller,
-            uniforms,
-            uniform_buffer,
-            uniform_bind_group,
+            settings,
+            display_buffer,
+            display_bind_group,
 
             surface,
             device,
@@ -342,21 +255,27 @@ impl State {
             sc_desc,
             swap_chain,
             size,
+            render_pipeline_layout,
             render_pipeline,
-            compute_pipeline,
-
-            frame_buffer,
-
-            render_bind_layout,
-            render_bind_group,
-
-            framebuffer_bind_group_layout,
-            framebuffer_bind_group,
-
-            vertex_bind_group,
+            display_args,
+
+            renderer,
+            denoiser,
+            job,
+            pass_budget,
+            submissions,
+            pending_error: None,
+            pending_screenshot: None,
+            shader_reload,
+            scene_watcher,
 
             mouse_pressed: false,
-        }
+            moving: false,
+            last_motion: None,
+            roi_start: None,
+            roi: None,
+            roi_preview: None,
+        })
     }
 
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -366,39 +285,149 @@ impl State {
         self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
         self.projection.resize(self.size.width, self.size.height);
 
-        self.frame_buffer
-            .resize(self.size.width, self.size.height, &self.device, &self.queue);
+        self.resize_frame_buffer();
 
-        let (fb_src_view, fb_dst_view) = self.frame_buffer.create_views();
+        println!("{:} {:}", self.size.width, self.size.height);
+    }
 
-        // Instantiates the bind group, once again specifying the binding of buffers.
-        self.framebuffer_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &self.framebuffer_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&fb_src_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&fb_dst_view),
-                },
-            ],
-        });
+    /// resizes the frame buffer to the render resolution, this restarts the accumulation
+    fn resize_frame_buffer(&mut self) {
+        let (width, height) = self.settings.resolution.render_size(
+            (self.size.width, self.size.height),
+            self.moving,
+            self.device.limits().max_texture_dimension_2d,
+        );
+        self.renderer
+            .resize(width, height, &self.device, &self.queue);
+        self.denoiser.resize(
+            &self.device,
+            &self.queue,
+            &self.renderer.frame_buffer,
+            self.renderer.render_bind_layout(),
+        );
+        if let Some((a, b)) = self.roi {
+            let region = self.frame_region(a, b);
+            self.renderer
+                .set_roi(&self.device, &self.queue, Some(region));
+        }
+        self.write_display_uniforms();
+    }
 
-        self.render_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &self.render_bind_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&fb_dst_view),
-            }],
-        });
+    fn write_display_uniforms(&self) {
+        let mut uniforms = display::DisplayUniforms::new(&self.settings.display)
+            .with_viewport(self.size.width, self.size.height);
+        if let Some(r) = self.roi_preview.or_else(|| self.renderer.roi()) {
+            uniforms = uniforms.with_roi([r.x, r.y, r.width, r.height]);
+        }
+        self.queue
+            .write_buffer(&self.display_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    }
 
-        self.uniforms.reset_pass();
+    /// changes the integrator settings with the keyboard.
+    /// Returns true if the key was handled
+    fn process_settings_key(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        if state != ElementState::Pressed {
+            return false;
+        }
+        let integrator = &mut self.settings.integrator;
+        match key {
+            VirtualKeyCode::LBracket => {
+                integrator.max_depth = integrator.max_depth.saturating_sub(1)
+            }
+            VirtualKeyCode::RBracket => integrator.max_depth += 1,
+            VirtualKeyCode::Comma => integrator.rr_depth = integrator.rr_depth.saturating_sub(1),
+            VirtualKeyCode::Period => integrator.rr_depth += 1,
+            VirtualKeyCode::Minus => {
+                integrator.radiance_clamp = (integrator.radiance_clamp - 1.0).max(0.0)
+            }
+            VirtualKeyCode::Equals => integrator.radiance_clamp += 1.0,
+            VirtualKeyCode::N => {
+                integrator.debug_view = integrator.debug_view.next();
+                print_legend(integrator, self.renderer.num_faces());
+            }
+            VirtualKeyCode::M => integrator.sampler = integrator.sampler.next(),
+            VirtualKeyCode::F => integrator.filter = integrator.filter.next(),
+            VirtualKeyCode::I => {
+                integrator.architecture = integrator.architecture.next();
+                if integrator.architecture == settings::Architecture::Wavefront
+                    && !renderer::supports_wavefront(&self.device.limits())
+                {
+                    eprintln!(
+                        "the device has too few storage buffers for the wavefront architecture"
+                    );
+                    integrator.architecture = settings::Architecture::Megakernel;
+                }
+            }
+            _ => return false,
+        }
+        println!("{:?}", integrator);
+        self.renderer
+            .update_integrator(&self.device, &self.queue, integrator);
+        true
+    }
 
-        println!("{:} {:}", self.size.width, self.size.height);
+    /// changes the display settings with the keyboard, the accumulation is kept.
+    /// Returns true if the key was handled
+    fn process_display_key(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        if state != ElementState::Pressed {
+            return false;
+        }
+        let display = &mut self.settings.display;
+        match key {
+            VirtualKeyCode::T => display.tone_mapper = display.tone_mapper.next(),
+            VirtualKeyCode::O => display.exposure -= 0.5,
+            VirtualKeyCode::P => display.exposure += 0.5,
+            VirtualKeyCode::K => display.white_balance = (display.white_balance - 500.).max(2000.),
+            VirtualKeyCode::L => display.white_balance = (display.white_balance + 500.).min(25000.),
+            VirtualKeyCode::U => display.upscale = display.upscale.next(),
+            _ => return false,
+        }
+        println!("{:?}", display);
+        self.write_display_uniforms();
+        true
+    }
+
+    /// changes the render resolution with the keyboard, this restarts the accumulation.
+    /// Returns true if the key was handled
+    fn process_resolution_key(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        if state != ElementState::Pressed {
+            return false;
+        }
+        let resolution = &mut self.settings.resolution;
+        let scale = match key {
+            VirtualKeyCode::Key1 => resolution.scale * 0.5,
+            VirtualKeyCode::Key2 => resolution.scale * 2.,
+            _ => return false,
+        };
+        // the keys switch from a fixed resolution back to scaling
+        resolution.size = None;
+        resolution.scale = scale
+            .max(settings::ResolutionSettings::MIN_SCALE)
+            .min(settings::ResolutionSettings::MAX_SCALE);
+        println!("{:?}", resolution);
+        self.resize_frame_buffer();
+        true
+    }
+
+    /// changes the denoise settings with the keyboard, the accumulation is kept.
+    /// Returns true if the key was handled
+    fn process_denoise_key(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        if state != ElementState::Pressed {
+            return false;
+        }
+        let denoise = &mut self.settings.denoise;
+        match key {
+            VirtualKeyCode::V => denoise.mode = denoise.mode.next(),
+            VirtualKeyCode::Key9 => {
+                denoise.iterations = denoise.iterations.saturating_sub(1).max(1)
+            }
+            VirtualKeyCode::Key0 => {
+                denoise.iterations = (denoise.iterations + 1).min(denoise::MAX_ITERATIONS)
+            }
+            _ => return false,
+        }
+        println!("{:?}", denoise);
+        true
     }
 
     // UPDATED!
@@ -409,7 +438,13 @@ impl State {
                 virtual_keycode: Some(key),
                 state,
                 ..
-            }) => self.camera_controller.process_keyboard(*key, *state),
+            }) => {
+                self.process_settings_key(*key, *state)
+                    || self.process_display_key(*key, *state)
+                    || self.process_denoise_key(*key, *state)
+                    || self.process_resolution_key(*key, *state)
+                    || self.camera_controller.process_keyboard(*key, *state)
+            }
             DeviceEvent::MouseWheel { delta, .. } => {
                 self.camera_controller.process_scroll(delta);
                 true
@@ -424,50 +459,157 @@ impl State {
         }
     }
 
-    fn update(&mut self, dt: std::time::Duration) {
+    fn update(&mut self, dt: Duration) {
         // UPDATED!
         let before = self.camera.calc_matrix();
         self.camera_controller.update_camera(&mut self.camera, dt);
         if self.camera.calc_matrix() != before {
-            self.uniforms.reset_pass();
-        }
-        self.uniforms
-            .increment_time((dt.as_millis() as f32) / 1000.);
-        self.uniforms
-            .update_view_proj(&self.camera, &self.projection);
-        self.queue.write_buffer(
-            &self.uniform_buffer,
-            0,
-            bytemuck::cast_slice(&[self.uniforms]),
+            self.last_motion = Some(Instant::now());
+        }
+        // frames without mouse motion in between moving ones must not reallocate the frame
+        // buffer back and forth
+        let moving = self
+            .last_motion
+            .map_or(false, |t| t.elapsed() < MOTION_REST_TIME);
+        if moving != self.moving {
+            self.moving = moving;
+            if self.settings.resolution.motion_scale < 1. {
+                self.resize_frame_buffer();
+            }
+        }
+        self.renderer.advance_time((dt.as_millis() as f32) / 1000.);
+        self.renderer.set_camera(&self.camera);
+
+        let shaders_changed = self
+            .shader_reload
+            .as_mut()
+            .map_or(false, |reload| reload.watcher.changed());
+        if shaders_changed {
+            match self.reload_shaders() {
+                Ok(()) => println!("reloaded the shaders"),
+                Err(err) => eprintln!("keeping the old shaders: {}", err),
+            }
+        }
+        let scene_changed = self
+            .scene_watcher
+            .as_mut()
+            .map_or(false, |watcher| watcher.changed());
+        if scene_changed {
+            self.reload_scene();
+        }
+    }
+
+    /// Reads the scene file again, the camera stays where it is.
+    /// Exporters may write the files in several steps, so a broken scene keeps the old one
+    /// until the next change
+    fn reload_scene(&mut self) {
+        let path = match &self.settings.scene {
+            Some(path) => path,
+            None => return,
+        };
+        match Scene::load(path) {
+            Ok(file) => {
+                self.renderer.set_scene(&self.device, &file.scene);
+                // the scene may reference other meshes now
+                self.scene_watcher = Some(watch::FileWatcher::new(file.files));
+                println!("reloaded {}", path.display());
+            }
+            Err(err) => eprintln!("keeping the old scene: {}", err),
+        }
+    }
+
+    /// files are watched, so the event loop has to wake up regularly
+    fn is_watching(&self) -> bool {
+        self.shader_reload.is_some() || self.scene_watcher.is_some()
+    }
+
+    /// recompiles the pipelines from the shader files, the accumulation restarts
+    fn reload_shaders(&mut self) -> Result<(), String> {
+        let errors = match &self.shader_reload {
+            Some(reload) => reload.errors.clone(),
+            None => return Ok(()),
+        };
+        let dir = Path::new(pipeline::SHADER_DIR);
+        let shaders = ShaderLibrary::load(dir)?;
+        shaders.validate("display.wgsl", &Defines::new())?;
+
+        self.renderer
+            .reload_shaders(&self.device, shaders.clone(), &errors)?;
+        let render_pipeline = create_display_pipeline(
+            &self.device,
+            &self.render_pipeline_layout,
+            self.sc_desc.format,
+            &shaders,
         );
+        errors
+            .take()
+            .map_err(|err| format!("display.wgsl: {}", err))?;
+        self.render_pipeline = render_pipeline;
+        Ok(())
     }
 
-    fn render(&mut self, _dt: std::time::Duration) -> Result<(), wgpu::SwapChainError> {
+    /// nothing changes on screen and no read back is pending, so only input has to wake
+    /// the event loop
+    fn is_idle(&self) -> bool {
+        self.job.is_done() && self.pending_error.is_none() && self.pending_screenshot.is_none()
+    }
+
+    fn render(&mut self, dt: Duration) -> Result<(), wgpu::SwapChainError> {
         //println!("{:} FPS",1000/(dt.as_millis()+1));
         let frame = self.swap_chain.get_current_frame()?.output;
 
-        let group_size = Vector2::new(32, 16);
-        let width_groups = lib::next_power_of_two(self.size.width / group_size.x);
-        let height_groups = lib::next_power_of_two(self.size.height / group_size.y);
-
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
 
-        {
-            let mut c_pass =
-                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
-            c_pass.set_pipeline(&self.compute_pipeline);
-            c_pass.set_bind_group(0, &self.framebuffer_bind_group, &[]);
-            c_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
-            c_pass.set_bind_group(2, &self.vertex_bind_group, &[]);
-            c_pass.insert_debug_marker("compute stuff");
-            // TODO use dispatch_indirect
-            c_pass.dispatch(width_groups, height_groups, 1); // Number of cells to run, the (x,y,z) size of item being processed
+        // a finished job only keeps presenting the accumulation
+        if self.renderer.passes() == 0 {
+            let frame_buffer = &self.renderer.frame_buffer;
+            self.job.restart(frame_buffer.width * frame_buffer.height);
+            // the error of the old accumulation does not matter anymore
+            self.pending_error = None;
+        }
+        let compute = !self.job.is_done();
+        if compute {
+            // a moving camera gets the fastest response, the passes of a frame are only
+            // shown at its end
+            let passes = if self.moving {
+                1
+            } else {
+                self.pass_budget.update(dt);
+                self.pass_budget.passes()
+            };
+            let passes = self
+                .job
+                .remaining_passes()
+                .map_or(passes, |r| r.min(passes));
+            self.renderer
+                .encode_passes(&self.queue, &mut encoder, passes);
+            let passes = self.renderer.passes();
+            if self.pending_error.is_none() && self.job.needs_error(passes) {
+                let read_back = self
+                    .renderer
+                    .frame_buffer
+                    .encode_read_back(&self.device, &mut encoder);
+                self.pending_error = Some(read_back);
+            }
         }
 
+        let frame_bind_group = if self.settings.denoise.mode != settings::DenoiseMode::Off {
+            self.denoiser.encode(
+                &mut encoder,
+                &self.queue,
+                &self.renderer.frame_buffer,
+                self.camera.calc_matrix(),
+                &self.settings.denoise,
+            );
+            self.denoiser.render_bind_group()
+        } else {
+            self.renderer.frame_buffer.render_bind_group()
+        };
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
@@ -483,42 +625,211 @@ impl State {
             });
 
             render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.render_bind_group, &[]);
-            // TODO use draw_indirect
-            render_pass.draw(0..6, 0..1);
+            render_pass.set_bind_group(0, frame_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.display_bind_group, &[]);
+            render_pass.draw_indirect(&self.display_args, 0);
         }
 
-        // copy last frame to (currently in dst buffer) to src buffer
-        encoder.copy_texture_to_texture(
-            wgpu::ImageCopyTexture {
-                texture: &self.frame_buffer.dst,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            wgpu::ImageCopyTexture {
-                texture: &self.frame_buffer.src,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            wgpu::Extent3d {
-                width: self.frame_buffer.width,
-                height: self.frame_buffer.height,
-                depth_or_array_layers: 1,
-            },
+        self.submissions.submit(&self.device, &self.queue, encoder);
+
+        let error = self.poll_error();
+        if compute {
+            if let Some(progress) = self.job.finish_pass(self.renderer.passes(), error) {
+                if progress.done {
+                    println!("render job finished: {}", progress);
+                } else {
+                    println!("{}", progress);
+                }
+            }
+        }
+        self.poll_screenshot();
+
+        Ok(())
+    }
+
+    /// the error of the accumulation once its read back arrived
+    fn poll_error(&mut self) -> Option<f32> {
+        let data = self.pending_error.as_mut()?.try_finish()?;
+        self.pending_error = None;
+        Some(data.mean_relative_error())
+    }
+
+    /// saves the screenshot once its read back arrived
+    fn poll_screenshot(&mut self) {
+        let screenshot = match &mut self.pending_screenshot {
+            Some(screenshot) => screenshot,
+            None => return,
+        };
+        let data = match screenshot.read_back.try_finish() {
+            Some(data) => data,
+            None => return,
+        };
+        let saved = capture::save(
+            std::path::Path::new("."),
+            &data,
+            screenshot.passes,
+            &screenshot.camera,
+            &screenshot.settings,
         );
+        match saved {
+            Ok(path) => println!("saved {}", path.display()),
+            Err(err) => eprintln!("{}", err),
+        }
+        self.pending_screenshot = None;
+    }
 
-        self.queue.submit(iter::once(encoder.finish()));
+    /// window position relative to the window size, it does not depend on the resolution
+    fn normalize(&self, position: (f64, f64)) -> (f64, f64) {
+        (
+            position.0 / self.size.width as f64,
+            position.1 / self.size.height as f64,
+        )
+    }
 
-        self.uniforms.increment_pass();
+    /// frame buffer pixels of the rectangle spanned by two normalized positions
+    fn frame_region(&self, a: (f64, f64), b: (f64, f64)) -> renderer::Region {
+        let frame_buffer = &self.renderer.frame_buffer;
+        let to_frame = |p: (f64, f64)| {
+            let x = p.0 * frame_buffer.width as f64;
+            let y = p.1 * frame_buffer.height as f64;
+            (
+                x.max(0.).min(frame_buffer.width as f64) as u32,
+                y.max(0.).min(frame_buffer.height as f64) as u32,
+            )
+        };
+        let (a, b) = (to_frame(a), to_frame(b));
+        renderer::Region {
+            x: a.0.min(b.0),
+            y: a.1.min(b.1),
+            width: a.0.max(b.0) - a.0.min(b.0),
+            height: a.1.max(b.1) - a.1.min(b.1),
+        }
+    }
 
-        self.device.poll(wgpu::Maintain::Wait);
+    fn start_roi(&mut self, position: (f64, f64)) {
+        self.roi_start = Some(self.normalize(position));
+    }
 
-        Ok(())
+    /// outlines the dragged region of interest
+    fn drag_roi(&mut self, position: (f64, f64)) {
+        if let Some(start) = self.roi_start {
+            self.roi_preview = Some(self.frame_region(start, self.normalize(position)));
+            self.write_display_uniforms();
+        }
+    }
+
+    /// restricts the sampling to the dragged region, a click without dragging clears it
+    fn finish_roi(&mut self, position: (f64, f64)) {
+        if let Some(start) = self.roi_start.take() {
+            let end = self.normalize(position);
+            let region = self.frame_region(start, end);
+            let region = Some(region).filter(|r| r.width > 0 && r.height > 0);
+            self.roi = region.map(|_| (start, end));
+            self.renderer.set_roi(&self.device, &self.queue, region);
+            match region {
+                Some(r) => println!("region of interest: {:?}", r),
+                None => println!("region of interest cleared"),
+            }
+            self.roi_preview = None;
+            self.write_display_uniforms();
+        }
+    }
+
+    /// Saves the current accumulation to the working directory.
+    /// The frame buffer is copied without waiting, it is saved by a later frame
+    fn screenshot(&mut self) {
+        if self.pending_screenshot.is_some() {
+            return;
+        }
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Screenshot Encoder"),
+            });
+        let read_back = self
+            .renderer
+            .frame_buffer
+            .encode_read_back(&self.device, &mut encoder);
+        self.submissions.submit(&self.device, &self.queue, encoder);
+        self.pending_screenshot = Some(PendingScreenshot {
+            read_back,
+            passes: self.renderer.passes(),
+            camera: self.camera.clone(),
+            settings: self.settings.clone(),
+        });
+    }
+}
+
+fn create_display_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    library: &ShaderLibrary,
+) -> wgpu::RenderPipeline {
+    pipeline::create_render_pipeline(
+        device,
+        layout,
+        format,
+        library
+            .module("display_shader", "display.wgsl", &Defines::new())
+            .expect("invalid display shader"),
+    )
+}
+
+/// prints the value range of the heat map legends
+fn print_legend(integrator: &settings::IntegratorSettings, num_faces: u32) {
+    match integrator.debug_view {
+        settings::DebugView::TraversalSteps => {
+            // one sphere and the faces, tested by the path and the shadow ray of every bounce
+            let primitives = 1 + num_faces;
+            println!(
+                "legend: 0 to {} primitive tests per path",
+                primitives * 2 * (integrator.max_depth + 1)
+            )
+        }
+        settings::DebugView::SampleCount => {
+            println!(
+                "legend: 1 to {} samples per pixel, logarithmic",
+                renderer::DEBUG_MAX_SAMPLES
+            )
+        }
+        _ => {}
     }
 }
 
 fn main() {
     env_logger::init();
+    let settings = match settings::Settings::from_args(std::env::args().skip(1)) {
+        Ok(settings) => settings,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("{}", err);
+            }
+            eprintln!("{}", settings::USAGE);
+            std::process::exit(1);
+        }
+    };
+    if settings.benchmark {
+        if let Err(err) = futures::executor::block_on(benchmark::run(&settings)) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if settings.offline.output.is_some() {
+        if let Err(err) = futures::executor::block_on(offline::render(&settings)) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let scene = match SceneFile::load_or_default(settings.scene.as_deref()) {
+        Ok(scene) => scene,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
     let event_loop = EventLoop::new();
     let title = env!("CARGO_PKG_NAME");
     let window = winit::window::WindowBuilder::new()
@@ -526,11 +837,25 @@ fn main() {
         .build(&event_loop)
         .unwrap();
     use futures::executor::block_on;
-    let mut global_state = block_on(State::new(&window)); // NEW!
-    let mut last_render_time = std::time::Instant::now();
+    let mut global_state = match block_on(State::new(&window, settings, scene)) {
+        Ok(state) => state,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let mut last_render_time = Instant::now();
     let mut last_pos: (f64, f64) = (0., 0.);
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
+        // once the job is done and the read backs arrived only input and the file watchers
+        // wake the loop up
+        *control_flow = if !global_state.is_idle() {
+            ControlFlow::Poll
+        } else if global_state.is_watching() {
+            ControlFlow::WaitUntil(Instant::now() + watch::CHECK_INTERVAL)
+        } else {
+            ControlFlow::Wait
+        };
         match event {
             Event::MainEventsCleared => window.request_redraw(),
             // UPDATED!
@@ -548,6 +873,11 @@ fn main() {
                     } => {
                         *control_flow = ControlFlow::Exit;
                     }
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F12),
+                        ..
+                    } => global_state.screenshot(),
                     keyboard_input => {
                         global_state.input(&DeviceEvent::Key(*keyboard_input));
                     }
@@ -557,6 +887,7 @@ fn main() {
                         delta: (position.x - last_pos.0, position.y - last_pos.1),
                     });
                     last_pos = (position.x, position.y);
+                    global_state.drag_roi(last_pos);
                 }
                 WindowEvent::MouseInput {
                     state,
@@ -565,6 +896,15 @@ fn main() {
                 } => {
                     global_state.mouse_pressed = *state == ElementState::Pressed;
                 }
+                // dragging with the right button selects the region of interest
+                WindowEvent::MouseInput {
+                    state,
+                    button: MouseButton::Right,
+                    ..
+                } => match state {
+                    ElementState::Pressed => global_state.start_roi(last_pos),
+                    ElementState::Released => global_state.finish_roi(last_pos),
+                },
                 WindowEvent::Resized(physical_size) => {
                     global_state.resize(*physical_size);
                 }
@@ -574,7 +914,7 @@ fn main() {
                 _ => {}
             },
             Event::RedrawRequested(_) => {
-                let now = std::time::Instant::now();
+                let now = Instant::now();
                 let dt = now - last_render_time;
                 last_render_time = now;
                 global_state.update(dt);
@@ -0,0 +1,142 @@
+use crate::scene::Vertex;
+
+/// index of a vertex in an `f` statement, negative indices count from the end
+fn parse_index(token: &str, vertices: usize) -> Option<u32> {
+    // v, v/vt, v//vn or v/vt/vn
+    let index: i64 = token.split('/').next()?.parse().ok()?;
+    let index = if index < 0 {
+        vertices as i64 + index
+    } else {
+        index - 1
+    };
+    if index < 0 || index >= vertices as i64 {
+        return None;
+    }
+    Some(index as u32)
+}
+
+/// Triangles of a Wavefront OBJ file
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub faces: Vec<[u32; 3]>,
+    /// `usemtl` statements, the index of the first face they apply to and the material name
+    pub usemtl: Vec<(usize, String)>,
+}
+
+/// Vertices, triangles and material names of a Wavefront OBJ file, the errors name the line.
+/// Polygons are split into fans of triangles, everything but positions, faces and `usemtl`
+/// is ignored
+pub fn parse(source: &str) -> Result<Mesh, String> {
+    let mut vertices = Vec::new();
+    let mut faces = Vec::new();
+    let mut usemtl = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", number + 1, message);
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let mut position = [0.; 3];
+                for p in position.iter_mut() {
+                    *p = tokens
+                        .next()
+                        .and_then(|t| t.parse().ok())
+                        .ok_or_else(|| error("invalid vertex position"))?;
+                }
+                vertices.push(Vertex { position });
+            }
+            Some("f") => {
+                let indices = tokens
+                    .map(|t| parse_index(t, vertices.len()))
+                    .collect::<Option<Vec<u32>>>()
+                    .ok_or_else(|| error("invalid vertex index"))?;
+                if indices.len() < 3 {
+                    return Err(error("faces need at least three vertices"));
+                }
+                for i in 1..indices.len() - 1 {
+                    faces.push([indices[0], indices[i], indices[i + 1]]);
+                }
+            }
+            Some("usemtl") => {
+                let name = tokens
+                    .next()
+                    .ok_or_else(|| error("usemtl without a material name"))?;
+                usemtl.push((faces.len(), name.to_string()));
+            }
+            _ => {}
+        }
+    }
+    Ok(Mesh {
+        vertices,
+        faces,
+        usemtl,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(mesh: &Mesh) -> Vec<[f32; 3]> {
+        mesh.vertices.iter().map(|v| v.position).collect()
+    }
+
+    #[test]
+    fn parses_vertices_and_faces() {
+        let mesh = parse("# triangle\nv 0 0 0\nv 1 0 0\nv 0 1.5 -2\nf 1 2 3\n").unwrap();
+        assert_eq!(
+            positions(&mesh),
+            [[0., 0., 0.], [1., 0., 0.], [0., 1.5, -2.]]
+        );
+        assert_eq!(mesh.faces, [[0, 1, 2]]);
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        let mesh = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 1 1 0\nf -1 -2 -3\n").unwrap();
+        assert_eq!(mesh.faces, [[0, 1, 2], [3, 2, 1]]);
+    }
+
+    #[test]
+    fn ignores_texture_and_normal_indices() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\nf 1/1/1 2//1 3/1\n";
+        assert_eq!(parse(source).unwrap().faces, [[0, 1, 2]]);
+    }
+
+    #[test]
+    fn splits_polygons_into_fans() {
+        let mesh = parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+        assert_eq!(mesh.faces, [[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn reports_invalid_lines() {
+        fn error(source: &str) -> String {
+            parse(source).err().unwrap()
+        }
+        let triangle = "v 0 0 0\nv 1 0 0\nv 0 1 0\n";
+        assert_eq!(
+            error(&format!("{}f 1 2 4", triangle)),
+            "line 4: invalid vertex index"
+        );
+        assert_eq!(
+            error(&format!("{}f 1 2 0", triangle)),
+            "line 4: invalid vertex index"
+        );
+        assert_eq!(
+            error(&format!("{}f -4 1 2", triangle)),
+            "line 4: invalid vertex index"
+        );
+        assert_eq!(
+            error(&format!("{}f 1 2", triangle)),
+            "line 4: faces need at least three vertices"
+        );
+        assert_eq!(error("v 0 0"), "line 1: invalid vertex position");
+        assert_eq!(error("usemtl"), "line 1: usemtl without a material name");
+    }
+
+    #[test]
+    fn records_the_faces_of_materials() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nusemtl red\nf 1 2 3\nf 1 2 3\n";
+        assert_eq!(parse(source).unwrap().usemtl, [(1, "red".to_string())]);
+    }
+}
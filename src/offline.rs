@@ -0,0 +1,235 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    checkpoint::Checkpoint,
+    cornell_box, denoise,
+    frame_buffer::{self, FrameBufferData},
+    job, oidn, output,
+    renderer::{self, Renderer, RendererSettings},
+    scene::SceneFile,
+    settings::{Architecture, DenoiseMode, JobSettings, Settings},
+    submission::SubmissionQueue,
+};
+
+/// passes submitted ahead of the GPU, the job checks its stop conditions meanwhile
+const PASSES_IN_FLIGHT: usize = 4;
+
+/// passes between the checks if adaptive sampling converged, every check waits for the GPU
+const CONVERGENCE_INTERVAL: u32 = 8;
+
+/// time between two checkpoints
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// tile size of images larger than the texture limit
+const DEFAULT_TILE_SIZE: u32 = 2048;
+
+/// Part of the image rendered with its own frame buffer
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// splits the image into rows of tiles, the tiles at the right and bottom edge may be smaller
+fn tiles(width: u32, height: u32, size: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(size as usize) {
+        for x in (0..width).step_by(size as usize) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: size.min(width - x),
+                height: size.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+/// Renders passes until the job is done, the accumulation is saved to `checkpoint` regularly
+fn render_passes(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    renderer: &mut Renderer,
+    job: &mut job::RenderJob,
+    checkpoint: Option<&Path>,
+) -> Result<(), String> {
+    let mut submissions = SubmissionQueue::new(device, PASSES_IN_FLIGHT);
+    let mut last_checkpoint = Instant::now();
+    while !job.is_done() {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Offline Encoder"),
+        });
+        renderer.encode_passes(queue, &mut encoder, 1);
+        submissions.submit(device, queue, encoder);
+
+        let error = if job.needs_error(renderer.passes()) {
+            Some(renderer.read_back(device, queue).mean_relative_error())
+        } else {
+            None
+        };
+        if let Some(progress) = job.finish_pass(renderer.passes(), error) {
+            report(&progress);
+        }
+
+        if let Some(checkpoint) = checkpoint {
+            if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                renderer.checkpoint(device, queue).save(checkpoint)?;
+                last_checkpoint = Instant::now();
+            }
+        }
+
+        if renderer.passes() % CONVERGENCE_INTERVAL == 0 && renderer.is_converged(device, queue) {
+            println!("all pixels converged after {} passes", renderer.passes());
+            break;
+        }
+    }
+    if let Some(checkpoint) = checkpoint {
+        renderer.checkpoint(device, queue).save(checkpoint)?;
+    }
+    Ok(())
+}
+
+/// prints the progress of a render job
+pub(crate) fn report(progress: &job::Progress) {
+    if progress.done {
+        println!("render job finished: {}", progress);
+    } else {
+        println!("{}", progress);
+    }
+}
+
+/// device and queue without a surface, created with the limits of the renderer
+pub(crate) async fn request_device(
+    architecture: Architecture,
+) -> Result<(wgpu::Device, wgpu::Queue), String> {
+    let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+        })
+        .await
+        .ok_or("no suitable graphics adapter found")?;
+    let limits = renderer::required_limits(&adapter.limits(), architecture)?;
+
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits,
+            },
+            None, // Trace path
+        )
+        .await
+        .map_err(|e| format!("failed to create the device: {}", e))
+}
+
+/// Renders the scene without a window and saves the result to `settings.offline.output`
+pub async fn render(settings: &Settings) -> Result<(), String> {
+    let offline = &settings.offline;
+    let path = offline.output.as_ref().expect("no output file given");
+
+    let (device, queue) = request_device(settings.integrator.architecture).await?;
+
+    let scene = SceneFile::load_or_default(settings.scene.as_deref())?;
+    let camera = scene.camera.unwrap_or_else(cornell_box::camera);
+
+    let max_size = device.limits().max_texture_dimension_2d;
+    let fits = offline.width <= max_size && offline.height <= max_size;
+    let default_tile_size = if fits {
+        offline.width.max(offline.height)
+    } else {
+        DEFAULT_TILE_SIZE
+    };
+    let tile_size = offline.tile_size.unwrap_or(default_tile_size);
+    let tiles = tiles(offline.width, offline.height, tile_size.min(max_size));
+    let tiled = tiles.len() > 1;
+    if tiled && offline.checkpoint.is_some() {
+        return Err("checkpoints are not supported by tiled renders".to_string());
+    }
+
+    let mut renderer = Renderer::new(
+        &device,
+        &queue,
+        &scene.scene,
+        &RendererSettings {
+            width: tiles[0].width,
+            height: tiles[0].height,
+            integrator: settings.integrator,
+        },
+    );
+    renderer.set_camera(&camera);
+
+    let mut data = if !tiled {
+        let mut job = job::RenderJob::new(settings.job, offline.width * offline.height);
+        if let Some(resume) = &offline.resume {
+            renderer.resume(&queue, &Checkpoint::load(resume)?)?;
+            println!("resumed after {} passes", renderer.passes());
+            if let Some(progress) = job.finish_pass(renderer.passes(), None) {
+                report(&progress);
+            }
+        }
+        render_passes(
+            &device,
+            &queue,
+            &mut renderer,
+            &mut job,
+            offline.checkpoint.as_deref(),
+        )?;
+        renderer.read_back(&device, &queue)
+    } else {
+        // the time budget is shared by the tiles
+        let job_settings = JobSettings {
+            time_limit: settings.job.time_limit.map(|t| t / tiles.len() as u32),
+            ..settings.job
+        };
+        // skip the AOVs of the whole image if nothing needs them, they take most of the memory
+        let needs_aovs = offline.aovs || offline.oidn || settings.denoise.mode != DenoiseMode::Off;
+        let aovs = if needs_aovs {
+            frame_buffer::AOV_NAMES.len()
+        } else {
+            0
+        };
+        let mut image = FrameBufferData::new(offline.width, offline.height, aovs);
+        for (i, tile) in tiles.iter().enumerate() {
+            println!(
+                "tile {}/{}: {}x{} at {}, {}",
+                i + 1,
+                tiles.len(),
+                tile.width,
+                tile.height,
+                tile.x,
+                tile.y
+            );
+            renderer.resize(tile.width, tile.height, &device, &queue);
+            renderer.set_tile([tile.x, tile.y], [offline.width, offline.height]);
+            let mut job = job::RenderJob::new(job_settings, tile.width * tile.height);
+            render_passes(&device, &queue, &mut renderer, &mut job, None)?;
+            image.paste(&renderer.read_back(&device, &queue), tile.x, tile.y);
+        }
+        image
+    };
+
+    let mut denoised = false;
+    if offline.oidn {
+        match oidn::denoise(&data) {
+            Ok(colors) => {
+                data.set_mean_color(&colors);
+                denoised = true;
+            }
+            Err(err) => eprintln!("{}, the image is not denoised by oidn", err),
+        }
+    }
+    if !denoised && settings.denoise.mode != DenoiseMode::Off {
+        // there is no history in offline renders, so svgf falls back to the spatial filter
+        let denoised = denoise::denoise(&data, &settings.denoise);
+        data.set_mean_color(&denoised);
+    }
+    output::save(&data, path, &settings.display, offline.aovs)
+}
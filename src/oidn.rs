@@ -0,0 +1,111 @@
+use crate::frame_buffer::FrameBufferData;
+
+/// minimal bindings of the OpenImageDenoise 1.x C API
+#[cfg(feature = "oidn")]
+mod ffi {
+    use std::os::raw::{c_char, c_int, c_void};
+
+    pub type Device = *mut c_void;
+    pub type Filter = *mut c_void;
+
+    pub const DEVICE_TYPE_DEFAULT: c_int = 0;
+    pub const FORMAT_FLOAT3: c_int = 3;
+    pub const ERROR_NONE: c_int = 0;
+
+    #[link(name = "OpenImageDenoise")]
+    extern "C" {
+        pub fn oidnNewDevice(device_type: c_int) -> Device;
+        pub fn oidnCommitDevice(device: Device);
+        pub fn oidnGetDeviceError(device: Device, message: *mut *const c_char) -> c_int;
+        pub fn oidnReleaseDevice(device: Device);
+        pub fn oidnNewFilter(device: Device, filter_type: *const c_char) -> Filter;
+        pub fn oidnSetSharedFilterImage(
+            filter: Filter,
+            name: *const c_char,
+            ptr: *mut c_void,
+            format: c_int,
+            width: usize,
+            height: usize,
+            byte_offset: usize,
+            byte_pixel_stride: usize,
+            byte_row_stride: usize,
+        );
+        pub fn oidnSetFilter1b(filter: Filter, name: *const c_char, value: bool);
+        pub fn oidnCommitFilter(filter: Filter);
+        pub fn oidnExecuteFilter(filter: Filter);
+        pub fn oidnReleaseFilter(filter: Filter);
+    }
+}
+
+/// Denoises the mean radiance with the RT filter of OpenImageDenoise,
+/// the albedo and normal AOVs are used as auxiliary images
+#[cfg(feature = "oidn")]
+pub fn denoise(frame: &FrameBufferData) -> Result<Vec<[f32; 3]>, String> {
+    use std::{ffi::CStr, os::raw::c_void, ptr};
+
+    let flatten = |values: Vec<[f32; 4]>| -> Vec<f32> {
+        values.iter().flat_map(|v| vec![v[0], v[1], v[2]]).collect()
+    };
+    let mut color: Vec<f32> = frame.mean_color().iter().flatten().copied().collect();
+    let mut albedo = flatten(frame.mean_aov(0));
+    let mut normal = flatten(frame.mean_aov(1));
+    let mut output = vec![0f32; color.len()];
+    let (width, height) = (frame.width as usize, frame.height as usize);
+
+    unsafe {
+        let device = ffi::oidnNewDevice(ffi::DEVICE_TYPE_DEFAULT);
+        if device.is_null() {
+            return Err("failed to create an OpenImageDenoise device".to_string());
+        }
+        ffi::oidnCommitDevice(device);
+
+        let filter = ffi::oidnNewFilter(device, b"RT\0".as_ptr() as _);
+        if filter.is_null() {
+            ffi::oidnReleaseDevice(device);
+            return Err("failed to create an OpenImageDenoise filter".to_string());
+        }
+        let mut images: [(&[u8], &mut Vec<f32>); 4] = [
+            (b"color\0", &mut color),
+            (b"albedo\0", &mut albedo),
+            (b"normal\0", &mut normal),
+            (b"output\0", &mut output),
+        ];
+        for (name, data) in images.iter_mut() {
+            ffi::oidnSetSharedFilterImage(
+                filter,
+                name.as_ptr() as _,
+                data.as_mut_ptr() as *mut c_void,
+                ffi::FORMAT_FLOAT3,
+                width,
+                height,
+                0,
+                0,
+                0,
+            );
+        }
+        ffi::oidnSetFilter1b(filter, b"hdr\0".as_ptr() as _, true);
+        ffi::oidnCommitFilter(filter);
+        ffi::oidnExecuteFilter(filter);
+
+        let mut message = ptr::null();
+        let error = ffi::oidnGetDeviceError(device, &mut message);
+        // the message belongs to the device, it has to be copied before the release
+        let message = if message.is_null() {
+            format!("error code {}", error)
+        } else {
+            CStr::from_ptr(message).to_string_lossy().into_owned()
+        };
+        ffi::oidnReleaseFilter(filter);
+        ffi::oidnReleaseDevice(device);
+        if error != ffi::ERROR_NONE {
+            return Err(format!("OpenImageDenoise failed: {}", message));
+        }
+    }
+
+    Ok(output.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect())
+}
+
+#[cfg(not(feature = "oidn"))]
+pub fn denoise(_frame: &FrameBufferData) -> Result<Vec<[f32; 3]>, String> {
+    Err("rey was built without the 'oidn' feature".to_string())
+}
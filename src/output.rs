@@ -0,0 +1,366 @@
+use std::{fs, path::Path};
+
+use crate::{display::DisplayUniforms, frame_buffer::FrameBufferData, settings::DisplaySettings};
+
+/// Pixel values of an EXR channel
+pub enum ChannelData {
+    Uint(Vec<u32>),
+    Float(Vec<f32>),
+}
+
+impl ChannelData {
+    /// pixel type as stored in the EXR header
+    fn pixel_type(&self) -> i32 {
+        match self {
+            ChannelData::Uint(_) => 0,
+            ChannelData::Float(_) => 2,
+        }
+    }
+
+    fn write_row(&self, y: usize, width: usize, out: &mut Vec<u8>) {
+        let row = y * width..(y + 1) * width;
+        match self {
+            ChannelData::Uint(values) => {
+                for v in values[row].iter() {
+                    out.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+            ChannelData::Float(values) => {
+                for v in values[row].iter() {
+                    out.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+fn write_attribute(out: &mut Vec<u8>, name: &str, ty: &str, value: &[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(ty.as_bytes());
+    out.push(0);
+    out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+/// Encodes an uncompressed scanline OpenEXR image.
+/// Channel names may contain a layer prefix, e.g. "albedo.R".
+pub fn encode_exr(width: u32, height: u32, mut channels: Vec<(String, ChannelData)>) -> Vec<u8> {
+    // readers expect the channels in alphabetical order
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut out = vec![0x76, 0x2f, 0x31, 0x01];
+    out.extend_from_slice(&2u32.to_le_bytes());
+
+    let mut chlist = Vec::new();
+    for (name, data) in channels.iter() {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&data.pixel_type().to_le_bytes());
+        // pLinear and reserved bytes
+        chlist.extend_from_slice(&[0; 4]);
+        // x and y sampling
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+
+    let mut window = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+
+    write_attribute(&mut out, "channels", "chlist", &chlist);
+    write_attribute(&mut out, "compression", "compression", &[0]);
+    write_attribute(&mut out, "dataWindow", "box2i", &window);
+    write_attribute(&mut out, "displayWindow", "box2i", &window);
+    write_attribute(&mut out, "lineOrder", "lineOrder", &[0]);
+    write_attribute(&mut out, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    write_attribute(&mut out, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(&mut out, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    out.push(0);
+
+    // every scanline is its own chunk as there is no compression
+    let (width, height) = (width as usize, height as usize);
+    let row_size: usize = channels.len() * width * 4;
+    let table_end = out.len() + height * 8;
+    for y in 0..height {
+        let offset = (table_end + y * (row_size + 8)) as u64;
+        out.extend_from_slice(&offset.to_le_bytes());
+    }
+    for y in 0..height {
+        out.extend_from_slice(&(y as i32).to_le_bytes());
+        out.extend_from_slice(&(row_size as i32).to_le_bytes());
+        for (_, data) in channels.iter() {
+            data.write_row(y, width, &mut out);
+        }
+    }
+    out
+}
+
+fn component(values: &[[f32; 4]], c: usize) -> ChannelData {
+    ChannelData::Float(values.iter().map(|v| v[c]).collect())
+}
+
+/// color of an id in the preview images, 0 (no hit) stays black
+fn id_color(id: u32) -> [u8; 3] {
+    if id == 0 {
+        return [0; 3];
+    }
+    let mut h = id.wrapping_mul(2654435761);
+    h ^= h >> 15;
+    h = h.wrapping_mul(2246822519);
+    h ^= h >> 13;
+    [h as u8, (h >> 8) as u8, (h >> 16) as u8]
+}
+
+fn save_rgb(path: &Path, width: u32, height: u32, pixels: Vec<u8>) -> Result<(), String> {
+    image::RgbImage::from_raw(width, height, pixels)
+        .expect("pixel buffer does not match the image size")
+        .save(path)
+        .map_err(|e| format!("failed to save '{}': {}", path.display(), e))
+}
+
+/// path of an AOV image next to the beauty image, e.g. render_normal.png
+fn aov_path(path: &Path, aov: &str) -> std::path::PathBuf {
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("render");
+    let name = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}_{}.{}", stem, aov, ext),
+        None => format!("{}_{}", stem, aov),
+    };
+    path.with_file_name(name)
+}
+
+/// Saves the mean of the accumulated samples.
+/// EXR files hold the linear radiance and the AOVs as layers, every other format is tone mapped
+/// and the AOVs are stored as separate images.
+pub fn save(
+    data: &FrameBufferData,
+    path: &Path,
+    display: &DisplaySettings,
+    aovs: bool,
+) -> Result<(), String> {
+    let color = data.mean_color();
+    let mean_aovs: Vec<Vec<[f32; 4]>> = if aovs {
+        (0..data.aovs.len()).map(|a| data.mean_aov(a)).collect()
+    } else {
+        Vec::new()
+    };
+    let (albedo, normal, position) = (0, 1, 2);
+    let is_exr = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |e| e.eq_ignore_ascii_case("exr"));
+
+    if is_exr {
+        let mut channels = vec![
+            (
+                "R".to_string(),
+                ChannelData::Float(color.iter().map(|c| c[0]).collect()),
+            ),
+            (
+                "G".to_string(),
+                ChannelData::Float(color.iter().map(|c| c[1]).collect()),
+            ),
+            (
+                "B".to_string(),
+                ChannelData::Float(color.iter().map(|c| c[2]).collect()),
+            ),
+        ];
+        if aovs {
+            for (c, name) in ["R", "G", "B"].iter().enumerate() {
+                channels.push((format!("albedo.{}", name), component(&mean_aovs[albedo], c)));
+            }
+            for (c, name) in ["X", "Y", "Z"].iter().enumerate() {
+                channels.push((format!("normal.{}", name), component(&mean_aovs[normal], c)));
+                channels.push((
+                    format!("position.{}", name),
+                    component(&mean_aovs[position], c),
+                ));
+            }
+            channels.push(("depth.Z".to_string(), component(&mean_aovs[position], 3)));
+            channels.push((
+                "id.material".to_string(),
+                ChannelData::Uint(data.ids.iter().map(|id| id[0]).collect()),
+            ));
+            channels.push((
+                "id.object".to_string(),
+                ChannelData::Uint(data.ids.iter().map(|id| id[1]).collect()),
+            ));
+        }
+        let bytes = encode_exr(data.width, data.height, channels);
+        return fs::write(path, bytes)
+            .map_err(|e| format!("failed to save '{}': {}", path.display(), e));
+    }
+
+    let uniforms = DisplayUniforms::new(display);
+    let beauty = color.iter().flat_map(|&c| uniforms.apply(c).to_vec());
+    save_rgb(path, data.width, data.height, beauty.collect())?;
+
+    if aovs {
+        use crate::display::to_srgb8;
+        let albedo = mean_aovs[albedo]
+            .iter()
+            .flat_map(|a| vec![to_srgb8(a[0]), to_srgb8(a[1]), to_srgb8(a[2])]);
+        save_rgb(
+            &aov_path(path, "albedo"),
+            data.width,
+            data.height,
+            albedo.collect(),
+        )?;
+
+        let to_unorm = |n: f32| ((n * 0.5 + 0.5).max(0.).min(1.) * 255. + 0.5) as u8;
+        let normal = mean_aovs[normal]
+            .iter()
+            .flat_map(|n| vec![to_unorm(n[0]), to_unorm(n[1]), to_unorm(n[2])]);
+        save_rgb(
+            &aov_path(path, "normal"),
+            data.width,
+            data.height,
+            normal.collect(),
+        )?;
+
+        // near is white, far is dark gray and the background black
+        let max_depth = mean_aovs[position].iter().fold(0f32, |m, p| m.max(p[3]));
+        let depth = mean_aovs[position].iter().flat_map(|p| {
+            let d = if p[3] > 0. {
+                255 - (p[3] / max_depth * 224.) as u8
+            } else {
+                0
+            };
+            vec![d; 3]
+        });
+        save_rgb(
+            &aov_path(path, "depth"),
+            data.width,
+            data.height,
+            depth.collect(),
+        )?;
+
+        let ids = data.ids.iter().flat_map(|id| id_color(id[1]).to_vec());
+        save_rgb(
+            &aov_path(path, "id"),
+            data.width,
+            data.height,
+            ids.collect(),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_i32(data: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+    }
+
+    fn read_string(data: &[u8], at: &mut usize) -> String {
+        let end = *at + data[*at..].iter().position(|&b| b == 0).unwrap();
+        let s = String::from_utf8(data[*at..end].to_vec()).unwrap();
+        *at = end + 1;
+        s
+    }
+
+    /// name, pixel type and raw values of a decoded channel
+    type Channel = (String, i32, Vec<u32>);
+
+    /// decodes what encode_exr writes: the channel names and pixel types, and the raw
+    /// 32 bit words of every channel read through the offset table
+    fn decode_exr(data: &[u8]) -> (i32, i32, Vec<Channel>) {
+        assert_eq!(data[..4], [0x76, 0x2f, 0x31, 0x01]);
+        assert_eq!(read_i32(data, 4), 2);
+        let mut at = 8;
+        let mut channels = Vec::new();
+        let mut window = [0; 4];
+        loop {
+            let name = read_string(data, &mut at);
+            if name.is_empty() {
+                break;
+            }
+            let ty = read_string(data, &mut at);
+            let size = read_i32(data, at) as usize;
+            at += 4;
+            let value = &data[at..at + size];
+            match (name.as_str(), ty.as_str()) {
+                ("channels", "chlist") => {
+                    let mut c = 0;
+                    while value[c] != 0 {
+                        let channel = read_string(value, &mut c);
+                        channels.push((channel, read_i32(value, c), Vec::new()));
+                        // the pixel type is followed by pLinear, reserved bytes and the sampling
+                        assert_eq!(read_i32(value, c + 8), 1);
+                        assert_eq!(read_i32(value, c + 12), 1);
+                        c += 16;
+                    }
+                }
+                ("compression", "compression") => assert_eq!(value, [0]),
+                ("dataWindow", "box2i") => {
+                    for (i, w) in window.iter_mut().enumerate() {
+                        *w = read_i32(value, 4 * i);
+                    }
+                }
+                _ => {}
+            }
+            at += size;
+        }
+
+        let (width, height) = (window[2] - window[0] + 1, window[3] - window[1] + 1);
+        let row_size = channels.len() * width as usize * 4;
+        for y in 0..height as usize {
+            let offset = &data[at + 8 * y..at + 8 * y + 8];
+            let mut chunk = u64::from_le_bytes([
+                offset[0], offset[1], offset[2], offset[3], offset[4], offset[5], offset[6],
+                offset[7],
+            ]) as usize;
+            assert_eq!(read_i32(data, chunk), y as i32);
+            assert_eq!(read_i32(data, chunk + 4) as usize, row_size);
+            chunk += 8;
+            for (_, _, values) in channels.iter_mut() {
+                for _ in 0..width {
+                    values.push(read_i32(data, chunk) as u32);
+                    chunk += 4;
+                }
+            }
+        }
+        let end = at + 8 * height as usize + height as usize * (row_size + 8);
+        assert_eq!(data.len(), end);
+        (width, height, channels)
+    }
+
+    #[test]
+    fn encodes_exr() {
+        let float = |v: &[f32]| ChannelData::Float(v.to_vec());
+        let bits = |v: &[f32]| v.iter().map(|f| f.to_bits()).collect::<Vec<_>>();
+        let r = [1., 2., 3., 4., 5., 6.];
+        let g = [0.5, -1., 0., 1e-3, 1e6, 7.];
+        let b = [0.; 6];
+        let ids = vec![0, 1, 2, u32::MAX, 7, 42];
+        let data = encode_exr(
+            3,
+            2,
+            vec![
+                ("R".to_string(), float(&r)),
+                ("id.object".to_string(), ChannelData::Uint(ids.clone())),
+                ("G".to_string(), float(&g)),
+                ("B".to_string(), float(&b)),
+            ],
+        );
+
+        let (width, height, channels) = decode_exr(&data);
+        assert_eq!((width, height), (3, 2));
+        assert_eq!(
+            channels,
+            vec![
+                ("B".to_string(), 2, bits(&b)),
+                ("G".to_string(), 2, bits(&g)),
+                ("R".to_string(), 2, bits(&r)),
+                ("id.object".to_string(), 0, ids),
+            ]
+        );
+    }
+}
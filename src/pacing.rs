@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use crate::renderer::MAX_PASSES_PER_SUBMIT;
+
+/// frames faster than the target by this factor get more passes
+const GROW_THRESHOLD: f32 = 1.25;
+/// frames slower than the target by this factor get less passes
+const SHRINK_THRESHOLD: f32 = 0.95;
+/// largest change of the pass count per frame, the frame times lag behind the submissions
+const MAX_STEP: f32 = 1.5;
+
+/// Number of compute passes recorded per presented frame, adapted so the frames take
+/// the target time. With vsync one pass per frame would cap the samples per second
+/// at the refresh rate
+pub struct PassBudget {
+    target: Duration,
+    passes: f32,
+}
+
+impl PassBudget {
+    pub fn new(target: Duration) -> Self {
+        Self { target, passes: 1. }
+    }
+
+    /// passes of the next frame
+    pub fn passes(&self) -> u32 {
+        self.passes as u32
+    }
+
+    /// Adapts the pass count to the duration of the last frame.
+    /// Vsync rounds the frame times up to multiples of the refresh interval,
+    /// so frames close to the target keep the count instead of oscillating around it
+    pub fn update(&mut self, frame_time: Duration) {
+        let ratio = self.target.as_secs_f32() / frame_time.as_secs_f32().max(1e-6);
+        if !(SHRINK_THRESHOLD..=GROW_THRESHOLD).contains(&ratio) {
+            let step = ratio.max(1. / MAX_STEP).min(MAX_STEP);
+            self.passes = (self.passes * step)
+                .max(1.)
+                .min(MAX_PASSES_PER_SUBMIT as f32);
+        }
+    }
+}
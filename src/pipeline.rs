@@ -1,3 +1,8 @@
+use std::sync::{Arc, Mutex};
+
+/// directory of the WGSL files in the source tree, read when the shaders are reloaded
+pub const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+
 pub fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
@@ -37,17 +42,69 @@ pub fn create_compute_pipeline(
     bind_group_layouts: &[&wgpu::BindGroupLayout],
     shader_src: wgpu::ShaderModuleDescriptor,
     label: Option<&str>,
+) -> wgpu::ComputePipeline {
+    create_compute_pipeline_with_entry(device, bind_group_layouts, shader_src, "main", label)
+}
+
+/// compute pipeline for shaders with several entry points
+pub fn create_compute_pipeline_with_entry(
+    device: &wgpu::Device,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    shader_src: wgpu::ShaderModuleDescriptor,
+    entry_point: &str,
+    label: Option<&str>,
 ) -> wgpu::ComputePipeline {
     let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label,
         bind_group_layouts,
         push_constant_ranges: &[],
     });
-    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
         label,
         layout: Some(&layout),
         module: &device.create_shader_module(&shader_src),
-        entry_point: "main",
-    });
-    pipeline
+        entry_point,
+    })
+}
+
+/// Parses and validates WGSL the way wgpu does when it creates the shader module,
+/// but returns the error message instead of reporting it to the device
+pub fn validate_wgsl(source: &str) -> Result<(), String> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|err| err.emit_to_string())?;
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all())
+        .validate(&module)
+        .map_err(|err| format!("validation error: {}", err))?;
+    Ok(())
+}
+
+/// Collects the errors of a device instead of panicking, so shaders which do not match
+/// their pipeline layout can be rejected while the old pipelines stay in use.
+/// The errors are logged as well
+#[derive(Clone, Default)]
+pub struct ErrorCapture {
+    errors: Arc<Mutex<Vec<String>>>,
+}
+
+impl ErrorCapture {
+    /// replaces the error handler of the device
+    pub fn install(device: &wgpu::Device) -> Self {
+        let capture = Self::default();
+        let errors = capture.errors.clone();
+        device.on_uncaptured_error(move |err: wgpu::Error| {
+            eprintln!("wgpu error: {}", err);
+            errors.lock().unwrap().push(err.to_string());
+        });
+        capture
+    }
+
+    /// fails with the errors since the last call
+    pub fn take(&self) -> Result<(), String> {
+        let mut errors = self.errors.lock().unwrap();
+        if errors.is_empty() {
+            return Ok(());
+        }
+        let message = errors.join("\n");
+        errors.clear();
+        Err(message)
+    }
 }
@@ -0,0 +1,846 @@
+use cgmath::prelude::*;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    adaptive, blue_noise, camera, checkpoint,
+    frame_buffer::{self, FrameBufferData},
+    indirect::DispatchArgs,
+    pipeline,
+    scene::{self, Material, Scene},
+    settings::{self, Architecture},
+    shader::{Defines, ShaderLibrary},
+    wavefront,
+};
+
+/// most compute passes recorded into one command encoder, see `Renderer::encode_passes`
+pub const MAX_PASSES_PER_SUBMIT: u32 = 64;
+
+/// workgroup size of the megakernel
+pub(crate) const MEGAKERNEL_GROUP_SIZE: [u32; 3] = [32, 16, 1];
+
+/// sample count mapped to the top of the sample count heat map, the scale is logarithmic
+pub const DEBUG_MAX_SAMPLES: u32 = 4096;
+
+/// constants of the path tracer WGSL, shared by the megakernel and the wavefront stages
+pub(crate) fn path_tracer_defines() -> Defines {
+    let [x, y, z] = MEGAKERNEL_GROUP_SIZE;
+    Defines::new()
+        .set_u32("MEGAKERNEL_GROUP_SIZE_X", x)
+        .set_u32("MEGAKERNEL_GROUP_SIZE_Y", y)
+        .set_u32("MEGAKERNEL_THREADS", x * y * z)
+        .set_u32("BLUE_NOISE_SIZE", blue_noise::SIZE as u32)
+        .set("DEBUG_MAX_SAMPLES", format!("{}.0", DEBUG_MAX_SAMPLES))
+        // array sizes have to be plain literals
+        .set("MAX_MATERIALS", scene::MAX_MATERIALS)
+}
+
+/// `SceneData` of common.wgsl without the materials, which follow it
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SceneHeader {
+    light: [f32; 4],
+    light_material: u32,
+    _padding: [u32; 3],
+}
+
+/// vertex, face and material buffers of the scene, the bind group keeps them alive
+fn create_scene_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    scene: &Scene,
+) -> wgpu::BindGroup {
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Vertex Buffer"),
+        contents: bytemuck::cast_slice(&scene.vertices),
+        usage: wgpu::BufferUsage::STORAGE,
+    });
+
+    let faces: Vec<[u32; 4]> = scene
+        .faces
+        .iter()
+        .zip(scene.face_materials.iter())
+        .map(|(f, &material)| [f[0], f[1], f[2], material])
+        .collect();
+    let face_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("face buffer"),
+        contents: bytemuck::cast_slice(&faces),
+        usage: wgpu::BufferUsage::STORAGE,
+    });
+
+    assert!(
+        scene.materials.len() < scene::MAX_MATERIALS,
+        "the scene has too many materials"
+    );
+    let light = &scene.light;
+    let header = SceneHeader {
+        light: [
+            light.center[0],
+            light.center[1],
+            light.center[2],
+            light.radius,
+        ],
+        light_material: scene.materials.len() as u32,
+        _padding: [0; 3],
+    };
+    let mut materials = scene.materials.clone();
+    materials.push(light.material);
+    materials.resize(scene::MAX_MATERIALS, Material { color: [0.; 4] });
+    let mut contents = bytemuck::bytes_of(&header).to_vec();
+    contents.extend_from_slice(bytemuck::cast_slice(&materials));
+    let scene_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("scene buffer"),
+        contents: &contents,
+        usage: wgpu::BufferUsage::UNIFORM,
+    });
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("vertex bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: vertex_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: face_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: scene_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+fn create_megakernel(
+    device: &wgpu::Device,
+    layouts: [&wgpu::BindGroupLayout; 4],
+    library: &ShaderLibrary,
+) -> wgpu::ComputePipeline {
+    pipeline::create_compute_pipeline(
+        device,
+        &layouts,
+        library
+            .module("compute_shader", "compute.wgsl", &path_tracer_defines())
+            .expect("invalid compute shader"),
+        Some("ComputePipeline"),
+    )
+}
+
+/// Uniforms of compute.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Uniforms {
+    view_proj: [[f32; 4]; 4],
+    time: f32,
+    pass: u32,
+    num_faces: u32,
+    max_depth: u32,
+    min_distance: f32,
+    rr_depth: u32,
+    radiance_clamp: f32,
+    debug_view: u32,
+    sampler: u32,
+    pixel_filter: u32,
+    filter_width: f32,
+    adaptive: u32,
+    tile_offset: [u32; 2],
+    image_size: [u32; 2],
+    roi_offset: [u32; 2],
+    roi_size: [u32; 2],
+}
+
+impl Uniforms {
+    fn new() -> Self {
+        Self {
+            view_proj: cgmath::Matrix4::identity().into(),
+            time: 0.0,
+            pass: 0,
+            num_faces: 0,
+            max_depth: 0,
+            min_distance: 0.0,
+            rr_depth: 0,
+            radiance_clamp: 0.0,
+            debug_view: 0,
+            sampler: 0,
+            pixel_filter: 0,
+            filter_width: 1.0,
+            adaptive: 0,
+            tile_offset: [0; 2],
+            image_size: [1; 2],
+            roi_offset: [0; 2],
+            roi_size: [0; 2],
+        }
+    }
+
+    pub fn increment_time(&mut self, dt: f32) {
+        self.time += dt;
+    }
+
+    fn increment_pass(&mut self) {
+        self.pass += 1;
+    }
+
+    pub fn reset_pass(&mut self) {
+        self.pass = 0;
+    }
+
+    /// places the frame buffer at `offset` in an image of `image_size` pixels,
+    /// without tiles the frame buffer is the whole image
+    pub fn set_tile(&mut self, offset: [u32; 2], image_size: [u32; 2]) {
+        self.tile_offset = offset;
+        self.image_size = image_size;
+    }
+
+    pub fn update_integrator(&mut self, settings: &settings::IntegratorSettings) {
+        self.max_depth = settings.max_depth;
+        self.min_distance = settings.min_distance;
+        self.rr_depth = settings.rr_depth;
+        self.radiance_clamp = settings.radiance_clamp;
+        self.debug_view = settings.debug_view as u32;
+        self.sampler = settings.sampler as u32;
+        self.pixel_filter = settings.filter as u32;
+        self.filter_width = settings.filter_width;
+    }
+
+    pub fn update_view_proj(&mut self, camera: &camera::Camera) {
+        self.view_proj = (camera.calc_matrix()).into() // TODO add perspective (ratio usw.)
+    }
+}
+
+/// Rectangle of frame buffer pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// storage buffers per shader stage of the wavefront stages, the megakernel gets along with
+/// the default
+const WAVEFRONT_STORAGE_BUFFERS: u32 = 8;
+
+/// true if a device with these limits can run the wavefront stages
+pub fn supports_wavefront(limits: &wgpu::Limits) -> bool {
+    limits.max_storage_buffers_per_shader_stage >= WAVEFRONT_STORAGE_BUFFERS
+}
+
+/// Limits to request from an adapter that supports the limits `supported`.
+/// The frame buffer binds more storage textures than the default allows. The storage buffers
+/// of the wavefront stages are only required when `architecture` is wavefront, otherwise they
+/// are requested if the adapter has them so the architecture can be switched later
+pub fn required_limits(
+    supported: &wgpu::Limits,
+    architecture: Architecture,
+) -> Result<wgpu::Limits, String> {
+    // color, AOVs and moments are read and written, the ids are only written
+    let storage_textures = 2 + 2 * frame_buffer::AOV_NAMES.len() as u32 + 1 + 2;
+    if supported.max_storage_textures_per_shader_stage < storage_textures {
+        return Err(format!(
+            "the adapter supports {} storage textures per shader stage, the frame buffer needs {}",
+            supported.max_storage_textures_per_shader_stage, storage_textures
+        ));
+    }
+    let wavefront = supports_wavefront(supported);
+    if architecture == Architecture::Wavefront && !wavefront {
+        return Err(format!(
+            "the adapter supports {} storage buffers per shader stage, the wavefront architecture needs {}",
+            supported.max_storage_buffers_per_shader_stage, WAVEFRONT_STORAGE_BUFFERS
+        ));
+    }
+    let defaults = wgpu::Limits::default();
+    Ok(wgpu::Limits {
+        max_storage_textures_per_shader_stage: storage_textures,
+        max_storage_buffers_per_shader_stage: if wavefront {
+            WAVEFRONT_STORAGE_BUFFERS
+        } else {
+            defaults.max_storage_buffers_per_shader_stage
+        },
+        ..defaults
+    })
+}
+
+/// Settings a `Renderer` is created with
+#[derive(Debug, Clone)]
+pub struct RendererSettings {
+    /// size of the frame buffer
+    pub width: u32,
+    pub height: u32,
+    pub integrator: settings::IntegratorSettings,
+}
+
+/// The path tracing part of the application: accumulates samples in the frame buffer.
+/// It does not know about windows, the device and queue are owned by the application.
+/// The device has to be created with `required_limits`
+pub struct Renderer {
+    uniforms: Uniforms,
+    uniform_buffer: wgpu::Buffer,
+    /// uniforms of every pass of one submission, copied to the uniform buffer before the pass
+    uniform_staging: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+
+    compute_pipeline: wgpu::ComputePipeline,
+    shaders: ShaderLibrary,
+
+    pub frame_buffer: frame_buffer::FrameBuffer,
+    framebuffer_bind_group_layout: wgpu::BindGroupLayout,
+    render_bind_layout: wgpu::BindGroupLayout,
+
+    uniform_bind_group_layout: wgpu::BindGroupLayout,
+    vertex_bind_group: wgpu::BindGroup,
+    vertex_bind_group_layout: wgpu::BindGroupLayout,
+
+    adaptive: adaptive::AdaptiveSampler,
+    /// stages of the wavefront architecture, the megakernel is used without it
+    wavefront: Option<wavefront::Wavefront>,
+    adaptive_enabled: bool,
+    /// region of interest, only these pixels are sampled
+    roi: Option<Region>,
+    /// see `Scene::hash`
+    scene_hash: u64,
+}
+
+impl Renderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        settings: &RendererSettings,
+    ) -> Self {
+        let (width, height) = (settings.width, settings.height);
+        let integrator = &settings.integrator;
+        let mut uniforms = Uniforms::new();
+        uniforms.num_faces = scene.faces.len() as u32;
+        uniforms.update_integrator(integrator);
+        uniforms.set_tile([0, 0], [width, height]);
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let uniform_staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Uniform Staging Buffer"),
+            size: MAX_PASSES_PER_SUBMIT as u64 * std::mem::size_of::<Uniforms>() as u64,
+            usage: wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let blue_noise_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Blue Noise Buffer"),
+            contents: bytemuck::cast_slice(blue_noise::mask()),
+            usage: wgpu::BufferUsage::STORAGE,
+        });
+
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("uniform_bind_layout"),
+            });
+
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: blue_noise_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("uniform_bind_group"),
+        });
+
+        let framebuffer_bind_group_layout =
+            frame_buffer::FrameBuffer::create_compute_layout(device);
+        let render_bind_layout = frame_buffer::FrameBuffer::create_render_layout(device);
+
+        let frame_buffer = frame_buffer::FrameBuffer::new(
+            width,
+            height,
+            device,
+            queue,
+            &framebuffer_bind_group_layout,
+            &render_bind_layout,
+        );
+
+        let vertex_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("vertex bind layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let vertex_bind_group = create_scene_bind_group(device, &vertex_bind_group_layout, scene);
+
+        let mut adaptive =
+            adaptive::AdaptiveSampler::new(device, &framebuffer_bind_group_layout, width, height);
+        adaptive.set_threshold(
+            integrator.adaptive_threshold,
+            integrator.adaptive_min_samples,
+        );
+        adaptive.write_uniforms(queue);
+
+        let shaders = ShaderLibrary::builtin();
+        let compute_pipeline = create_megakernel(
+            device,
+            [
+                &framebuffer_bind_group_layout,
+                &uniform_bind_group_layout,
+                &vertex_bind_group_layout,
+                adaptive.list_layout(),
+            ],
+            &shaders,
+        );
+
+        let mut renderer = Self {
+            uniforms,
+            uniform_buffer,
+            uniform_staging,
+            uniform_bind_group,
+
+            compute_pipeline,
+            shaders,
+
+            frame_buffer,
+            framebuffer_bind_group_layout,
+            render_bind_layout,
+
+            uniform_bind_group_layout,
+            vertex_bind_group,
+            vertex_bind_group_layout,
+
+            adaptive,
+            wavefront: None,
+            adaptive_enabled: integrator.adaptive_threshold > 0.,
+            roi: None,
+            scene_hash: scene.hash(),
+        };
+        renderer.set_architecture(device, integrator.architecture);
+        renderer
+    }
+
+    /// creates the wavefront stages or frees them, the result does not depend on the architecture
+    fn set_architecture(&mut self, device: &wgpu::Device, architecture: Architecture) {
+        match architecture {
+            Architecture::Megakernel => self.wavefront = None,
+            Architecture::Wavefront if self.wavefront.is_none() => {
+                self.wavefront = Some(self.create_wavefront(device, &self.shaders))
+            }
+            Architecture::Wavefront => {}
+        }
+    }
+
+    fn create_wavefront(
+        &self,
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+    ) -> wavefront::Wavefront {
+        wavefront::Wavefront::new(
+            device,
+            [
+                &self.framebuffer_bind_group_layout,
+                &self.uniform_bind_group_layout,
+                &self.vertex_bind_group_layout,
+            ],
+            shaders,
+            self.adaptive.pixel_list(),
+            self.frame_buffer.width,
+            self.frame_buffer.height,
+        )
+    }
+
+    /// Replaces the shaders of the path tracer and resets the accumulation.
+    /// If they do not validate, or the device reports errors while the pipelines are
+    /// created, the old pipelines are kept and the errors are returned
+    pub fn reload_shaders(
+        &mut self,
+        device: &wgpu::Device,
+        shaders: ShaderLibrary,
+        errors: &pipeline::ErrorCapture,
+    ) -> Result<(), String> {
+        shaders.validate("compute.wgsl", &path_tracer_defines())?;
+        shaders.validate("wavefront.wgsl", &wavefront::defines())?;
+        // errors of earlier work do not belong to the new pipelines
+        errors.take().ok();
+        let compute_pipeline = create_megakernel(
+            device,
+            [
+                &self.framebuffer_bind_group_layout,
+                &self.uniform_bind_group_layout,
+                &self.vertex_bind_group_layout,
+                self.adaptive.list_layout(),
+            ],
+            &shaders,
+        );
+        let wavefront = self
+            .wavefront
+            .as_ref()
+            .map(|_| self.create_wavefront(device, &shaders));
+        errors.take()?;
+
+        self.compute_pipeline = compute_pipeline;
+        if wavefront.is_some() {
+            self.wavefront = wavefront;
+        }
+        self.shaders = shaders;
+        self.reset();
+        Ok(())
+    }
+
+    /// moves the camera, the accumulation restarts if the view changed
+    pub fn set_camera(&mut self, camera: &camera::Camera) {
+        let view_proj = self.uniforms.view_proj;
+        self.uniforms.update_view_proj(camera);
+        if self.uniforms.view_proj != view_proj {
+            self.uniforms.reset_pass();
+        }
+    }
+
+    /// Replaces the geometry, e.g. when the scene file changed.
+    /// The camera is kept and the accumulation restarts
+    pub fn set_scene(&mut self, device: &wgpu::Device, scene: &Scene) {
+        self.vertex_bind_group =
+            create_scene_bind_group(device, &self.vertex_bind_group_layout, scene);
+        self.uniforms.num_faces = scene.faces.len() as u32;
+        self.scene_hash = scene.hash();
+        self.reset();
+    }
+
+    /// discards the accumulated samples
+    pub fn reset(&mut self) {
+        self.uniforms.reset_pass();
+    }
+
+    /// seconds since the start, not used by the shader yet
+    pub fn advance_time(&mut self, dt: f32) {
+        self.uniforms.increment_time(dt);
+    }
+
+    /// Renders the frame buffer as the tile at `offset` of a larger image,
+    /// the camera rays and random numbers are the ones of the image.
+    /// Resizing the renderer makes the frame buffer the whole image again
+    pub fn set_tile(&mut self, offset: [u32; 2], image_size: [u32; 2]) {
+        self.uniforms.set_tile(offset, image_size);
+        self.uniforms.reset_pass();
+    }
+
+    /// adds one sample to every pixel, a shortcut for `encode_passes` and a submit
+    pub fn render_pass(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Pass Encoder"),
+        });
+        self.encode_passes(queue, &mut encoder, 1);
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    /// copies the latest accumulation to the cpu
+    pub fn read_back(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> FrameBufferData {
+        self.frame_buffer.read_back(device, queue)
+    }
+
+    /// layout of the bind group returned by `FrameBuffer::render_bind_group`
+    pub fn render_bind_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.render_bind_layout
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.frame_buffer.resize(
+            width,
+            height,
+            device,
+            queue,
+            &self.framebuffer_bind_group_layout,
+            &self.render_bind_layout,
+        );
+        self.adaptive.resize(device, width, height);
+        self.adaptive.write_uniforms(queue);
+        if let Some(wavefront) = &mut self.wavefront {
+            wavefront.resize(device, self.adaptive.pixel_list(), width, height);
+        }
+        self.uniforms.set_tile([0, 0], [width, height]);
+        self.uniforms.reset_pass();
+        self.roi = None;
+    }
+
+    /// Restricts the sampling to a region, the other pixels keep their current value.
+    /// The region is cleared when the frame buffer is resized
+    pub fn set_roi(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, roi: Option<Region>) {
+        let roi = roi.filter(|r| r.width > 0 && r.height > 0);
+        if let Some(r) = roi {
+            assert!(
+                r.x + r.width <= self.frame_buffer.width
+                    && r.y + r.height <= self.frame_buffer.height,
+                "the region is outside of the frame buffer"
+            );
+            // both textures have to hold the frozen pixels as they are used alternately
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("ROI Encoder"),
+            });
+            self.frame_buffer.encode_copy_to_next(&mut encoder);
+            queue.submit(std::iter::once(encoder.finish()));
+        }
+        self.roi = roi;
+    }
+
+    pub fn roi(&self) -> Option<Region> {
+        self.roi
+    }
+
+    /// the sampled pixels, the whole frame buffer without a region of interest
+    fn sampled_region(&self) -> Region {
+        self.roi.unwrap_or(Region {
+            x: 0,
+            y: 0,
+            width: self.frame_buffer.width,
+            height: self.frame_buffer.height,
+        })
+    }
+
+    /// applies changed integrator settings and restarts the accumulation
+    pub fn update_integrator(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: &settings::IntegratorSettings,
+    ) {
+        self.set_architecture(device, settings.architecture);
+        self.uniforms.update_integrator(settings);
+        self.uniforms.reset_pass();
+        self.adaptive
+            .set_threshold(settings.adaptive_threshold, settings.adaptive_min_samples);
+        self.adaptive.write_uniforms(queue);
+        self.adaptive_enabled = settings.adaptive_threshold > 0.;
+    }
+
+    /// the uniforms of the next pass
+    fn pass_uniforms(&self) -> Uniforms {
+        let region = self.sampled_region();
+        Uniforms {
+            adaptive: self.is_adaptive_pass() as u32,
+            roi_offset: [region.x, region.y],
+            roi_size: [region.width, region.height],
+            ..self.uniforms
+        }
+    }
+
+    /// Records `count` compute passes which add one sample to every pixel each.
+    /// With adaptive sampling only the pixels above the noise threshold get a sample.
+    /// The uniforms of the passes are staged in one buffer, so the encoder has to be
+    /// submitted before passes are encoded again
+    pub fn encode_passes(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        count: u32,
+    ) {
+        assert!(
+            count <= MAX_PASSES_PER_SUBMIT,
+            "at most MAX_PASSES_PER_SUBMIT passes can be encoded at once"
+        );
+        if count == 0 {
+            return;
+        }
+        let size = std::mem::size_of::<Uniforms>() as u64;
+        let mut staged = Vec::with_capacity(count as usize);
+        for i in 0..count as u64 {
+            staged.push(self.pass_uniforms());
+            encoder.copy_buffer_to_buffer(
+                &self.uniform_staging,
+                i * size,
+                &self.uniform_buffer,
+                0,
+                size,
+            );
+            self.encode_pass(encoder);
+        }
+        queue.write_buffer(&self.uniform_staging, 0, bytemuck::cast_slice(&staged));
+    }
+
+    /// records one compute pass, the uniforms of the pass have to be in the uniform buffer
+    fn encode_pass(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let region = self.sampled_region();
+
+        let adaptive = self.is_adaptive_pass();
+        if adaptive {
+            self.adaptive
+                .encode(encoder, self.frame_buffer.compute_bind_group());
+        }
+
+        if let Some(wavefront) = &self.wavefront {
+            // the pixel list covers the whole frame buffer
+            let samples = if adaptive {
+                self.frame_buffer.width * self.frame_buffer.height
+            } else {
+                region.width * region.height
+            };
+            wavefront.encode(
+                encoder,
+                [
+                    self.frame_buffer.compute_bind_group(),
+                    &self.uniform_bind_group,
+                    &self.vertex_bind_group,
+                ],
+                samples,
+                self.uniforms.max_depth,
+            );
+        } else {
+            let mut c_pass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            c_pass.set_pipeline(&self.compute_pipeline);
+            c_pass.set_bind_group(0, self.frame_buffer.compute_bind_group(), &[]);
+            c_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
+            c_pass.set_bind_group(2, &self.vertex_bind_group, &[]);
+            c_pass.set_bind_group(3, self.adaptive.list_bind_group(), &[]);
+            c_pass.insert_debug_marker("compute stuff");
+            if adaptive {
+                c_pass.dispatch_indirect(self.adaptive.dispatch_args(), 0);
+            } else {
+                let args = DispatchArgs::for_threads(
+                    [region.width, region.height, 1],
+                    MEGAKERNEL_GROUP_SIZE,
+                );
+                c_pass.dispatch(args.x, args.y, args.z);
+            }
+        }
+
+        // the textures written by the compute pass hold the latest accumulation now
+        self.frame_buffer.swap();
+        self.uniforms.increment_pass();
+    }
+
+    /// triangles of the current scene
+    pub fn num_faces(&self) -> u32 {
+        self.uniforms.num_faces
+    }
+
+    /// number of compute passes since the last reset,
+    /// with adaptive sampling converged pixels have less samples
+    pub fn passes(&self) -> u32 {
+        self.uniforms.pass
+    }
+
+    /// the next pass only samples the pixels which are not converged yet
+    fn is_adaptive_pass(&self) -> bool {
+        // the pixel list covers the whole frame buffer
+        self.adaptive_enabled
+            && self.roi.is_none()
+            && self.uniforms.pass >= self.adaptive.min_samples().max(1)
+    }
+
+    /// Fingerprint of everything a continued accumulation has to agree on:
+    /// the scene, the camera, the integrator settings and the resolution
+    pub fn scene_hash(&self) -> u64 {
+        let uniforms = Uniforms {
+            time: 0.,
+            pass: 0,
+            adaptive: 0,
+            ..self.uniforms
+        };
+        let size = [self.frame_buffer.width, self.frame_buffer.height];
+        checkpoint::hash(&[
+            &self.scene_hash.to_le_bytes(),
+            bytemuck::cast_slice(&[uniforms]),
+            bytemuck::cast_slice(&size),
+        ])
+    }
+
+    pub fn checkpoint(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> checkpoint::Checkpoint {
+        checkpoint::Checkpoint {
+            scene_hash: self.scene_hash(),
+            pass: self.uniforms.pass,
+            data: self.frame_buffer.read_back(device, queue),
+        }
+    }
+
+    /// continues the accumulation of a checkpoint, fails if it was rendered with another scene
+    pub fn resume(
+        &mut self,
+        queue: &wgpu::Queue,
+        checkpoint: &checkpoint::Checkpoint,
+    ) -> Result<(), String> {
+        let data = &checkpoint.data;
+        if data.width != self.frame_buffer.width || data.height != self.frame_buffer.height {
+            return Err(format!(
+                "the checkpoint has a resolution of {}x{}, not {}x{}",
+                data.width, data.height, self.frame_buffer.width, self.frame_buffer.height
+            ));
+        }
+        if data.aovs.len() != frame_buffer::AOV_NAMES.len() {
+            return Err("the checkpoint has different AOVs".to_string());
+        }
+        if checkpoint.scene_hash != self.scene_hash() {
+            return Err(
+                "the checkpoint was rendered with a different scene, camera or integrator settings"
+                    .to_string(),
+            );
+        }
+        self.frame_buffer.upload(queue, data);
+        self.uniforms.pass = checkpoint.pass;
+        Ok(())
+    }
+
+    /// true if adaptive sampling is enabled and every pixel is below the noise threshold.
+    /// Waits for the GPU, so it should not be called every pass
+    pub fn is_converged(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        self.adaptive_enabled
+            && self.uniforms.pass > self.adaptive.min_samples()
+            && self.adaptive.remaining(device, queue) == 0
+    }
+}
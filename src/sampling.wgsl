@@ -0,0 +1,221 @@
+// Random numbers, samplers and pixel filters
+
+#include "common.wgsl"
+
+// every bounce uses its own set of dimensions, the first set is used by the camera
+let DIMENSIONS_PER_BOUNCE: u32 = 4u;
+
+// BLUE_NOISE_SIZE is the size of the tile in blue_noise, defined by renderer.rs
+
+// generator matrices of the first four sobol dimensions
+let sobol_directions: array<u32, 128> = array<u32, 128>(
+    2147483648u, 1073741824u, 536870912u, 268435456u, 134217728u, 67108864u, 33554432u, 16777216u,
+    8388608u, 4194304u, 2097152u, 1048576u, 524288u, 262144u, 131072u, 65536u,
+    32768u, 16384u, 8192u, 4096u, 2048u, 1024u, 512u, 256u,
+    128u, 64u, 32u, 16u, 8u, 4u, 2u, 1u,
+    2147483648u, 3221225472u, 2684354560u, 4026531840u, 2281701376u, 3422552064u, 2852126720u, 4278190080u,
+    2155872256u, 3233808384u, 2694840320u, 4042260480u, 2290614272u, 3435921408u, 2863267840u, 4294901760u,
+    2147516416u, 3221274624u, 2684395520u, 4026593280u, 2281736192u, 3422604288u, 2852170240u, 4278255360u,
+    2155905152u, 3233857728u, 2694881440u, 4042322160u, 2290649224u, 3435973836u, 2863311530u, 4294967295u,
+    2147483648u, 3221225472u, 1610612736u, 2415919104u, 3892314112u, 1543503872u, 2382364672u, 3305111552u,
+    1753219072u, 2629828608u, 3999268864u, 1435500544u, 2154299392u, 3231449088u, 1626210304u, 2421489664u,
+    3900735488u, 1556135936u, 2388680704u, 3314585600u, 1751705600u, 2627492864u, 4008611328u, 1431684352u,
+    2147543168u, 3221249216u, 1610649184u, 2415969680u, 3892340840u, 1543543964u, 2382425838u, 3305133397u,
+    2147483648u, 3221225472u, 536870912u, 1342177280u, 4160749568u, 1946157056u, 2717908992u, 2466250752u,
+    3632267264u, 624951296u, 1507852288u, 3872391168u, 2013790208u, 3020685312u, 2181169152u, 3271884800u,
+    546275328u, 1363623936u, 4226424832u, 1977167872u, 2693105664u, 2437829632u, 3689389568u, 635137280u,
+    1484783744u, 3846176960u, 2044723232u, 3067084880u, 2148008184u, 3222012020u, 537002146u, 1342505107u,
+);
+
+var seed: u32 = 0u;
+var pixel_seed: u32 = 0u;
+var pixel: vec2<u32> = vec2<u32>(0u, 0u);
+var sample_dimension: u32 = 0u;
+
+fn hash(x: u32) -> u32 {
+    var h: u32 = x;
+    h = h ^ (h >> 16u);
+    h = h * 2146121005u;
+    h = h ^ (h >> 15u);
+    h = h * 2221713035u;
+    h = h ^ (h >> 16u);
+    return h;
+}
+
+fn hash_combine(key: u32, v: u32) -> u32 {
+    return key ^ (hash(v) + (key << 6u) + (key >> 2u));
+}
+
+fn reverse_bits(x: u32) -> u32 {
+    var v: u32 = x;
+    v = ((v >> 1u) & 1431655765u) | ((v & 1431655765u) << 1u);
+    v = ((v >> 2u) & 858993459u) | ((v & 858993459u) << 2u);
+    v = ((v >> 4u) & 252645135u) | ((v & 252645135u) << 4u);
+    v = ((v >> 8u) & 16711935u) | ((v & 16711935u) << 8u);
+    return (v >> 16u) | (v << 16u);
+}
+
+// source: Burley, "Practical Hash-based Owen Scrambling"
+// with the improved hash of https://psychopath.io/post/2021_01_30_building_a_better_lk_hash
+fn laine_karras_permutation(x: u32, key: u32) -> u32 {
+    var v: u32 = x;
+    v = v ^ (v * 1025551850u);
+    v = v + key;
+    v = v * ((key >> 16u) | 1u);
+    v = v ^ (v * 89287766u);
+    v = v ^ (v * 1403136100u);
+    return v;
+}
+
+fn nested_uniform_scramble(x: u32, key: u32) -> u32 {
+    return reverse_bits(laine_karras_permutation(reverse_bits(x), key));
+}
+
+fn sobol(index: u32, dimension: u32) -> u32 {
+    var result: u32 = 0u;
+    var i: u32 = index;
+    for (var bit: u32 = 0u; i != 0u; bit = bit + 1u) {
+        if ((i & 1u) != 0u) {
+            result = result ^ sobol_directions[dimension * 32u + bit];
+        }
+        i = i >> 1u;
+    }
+    return result;
+}
+
+// owen-scrambled sobol, the dimensions are padded with shuffled 4D sets
+fn sobol_owen(index: u32, dimension: u32, key: u32) -> f32 {
+    let set_key = hash_combine(key, dimension / 4u);
+    let shuffled = nested_uniform_scramble(index, set_key);
+    let d = dimension % 4u;
+    let x = nested_uniform_scramble(sobol(shuffled, d), hash_combine(set_key, d + 1u));
+    return f32(x >> 8u) / 16777216.0;
+}
+
+fn blue_noise_value(dimension: u32) -> f32 {
+    // shift the tile for every dimension to decorrelate them
+    let offset = vec2<u32>(dimension * 3242174889u, dimension * 2447445413u) >> vec2<u32>(26u, 26u);
+    let p = (pixel + offset) % vec2<u32>(BLUE_NOISE_SIZE, BLUE_NOISE_SIZE);
+    return blue_noise.data[p.y * BLUE_NOISE_SIZE + p.x];
+}
+
+fn pcg() -> f32 {
+    seed = seed * 747796405u + 1u;
+    var word: u32 = ((seed >> ((seed >> 28u) + 4u)) ^ seed) * 277803737u;
+    word = (word >> 22u) ^ word;
+    return f32(word) / 4294967295.0;
+}
+
+fn init_sampler(pix: vec2<u32>, width: u32) {
+    pixel = pix;
+    pixel_seed = hash(pix.y * width + pix.x);
+    seed = hash_combine(pixel_seed, uniforms.pass);
+    sample_dimension = 0u;
+}
+
+// moves the sampler to the dimensions of the given bounce
+fn start_bounce(bounce: u32) {
+    sample_dimension = (bounce + 1u) * DIMENSIONS_PER_BOUNCE;
+}
+
+fn random() -> f32 {
+    let dimension = sample_dimension;
+    sample_dimension = sample_dimension + 1u;
+    if (uniforms.sampler == SAMPLER_SOBOL) {
+        return sobol_owen(uniforms.pass, dimension, pixel_seed);
+    }
+    if (uniforms.sampler == SAMPLER_BLUE_NOISE) {
+        // blue-noise dithered sampling: all pixels share one sequence
+        // which is rotated by a blue noise mask
+        return fract(sobol_owen(uniforms.pass, dimension, 0u) + blue_noise_value(dimension));
+    }
+    return pcg();
+}
+
+// pixel reconstruction filters selectable with uniforms.pixel_filter
+let FILTER_BOX: u32 = 0u;
+let FILTER_TENT: u32 = 1u;
+let FILTER_GAUSSIAN: u32 = 2u;
+let FILTER_MITCHELL: u32 = 3u;
+
+let GAUSSIAN_SIGMA: f32 = 0.5;
+let MITCHELL_B: f32 = 0.33333333;
+let MITCHELL_C: f32 = 0.33333333;
+
+// the random sampler cycles through PIXEL_STRATA x PIXEL_STRATA strata per pixel
+let PIXEL_STRATA: u32 = 4u;
+
+struct FilterSample {
+    offset: vec2<f32>;
+    weight: f32;
+};
+
+fn sample_tent(u: f32, radius: f32) -> f32 {
+    if (u < 0.5) {
+        return radius * (sqrt(2.0 * u) - 1.0);
+    }
+    return radius * (1.0 - sqrt(2.0 - 2.0 * u));
+}
+
+fn tent_pdf(x: f32, radius: f32) -> f32 {
+    return max(radius - abs(x), 0.0) / (radius * radius);
+}
+
+fn mitchell(x: f32) -> f32 {
+    let B = MITCHELL_B;
+    let C = MITCHELL_C;
+    let ax = abs(x);
+    if (ax < 1.0) {
+        return ((12.0 - 9.0 * B - 6.0 * C) * ax * ax * ax
+            + (-18.0 + 12.0 * B + 6.0 * C) * ax * ax
+            + (6.0 - 2.0 * B)) / 6.0;
+    }
+    if (ax < 2.0) {
+        return ((-B - 6.0 * C) * ax * ax * ax
+            + (6.0 * B + 30.0 * C) * ax * ax
+            + (-12.0 * B - 48.0 * C) * ax
+            + (8.0 * B + 24.0 * C)) / 6.0;
+    }
+    return 0.0;
+}
+
+// sub-pixel position in [0,1)^2, stratified over the passes for the random sampler
+// (the sobol samplers are already stratified)
+fn pixel_sample() -> vec2<f32> {
+    let u = vec2<f32>(random(), random());
+    if (uniforms.sampler != SAMPLER_RANDOM) {
+        return u;
+    }
+    let stratum = (uniforms.pass + pixel_seed) % (PIXEL_STRATA * PIXEL_STRATA);
+    let cell = vec2<f32>(f32(stratum % PIXEL_STRATA), f32(stratum / PIXEL_STRATA));
+    return (cell + u) / f32(PIXEL_STRATA);
+}
+
+// importance samples the pixel filter, the offset is relative to the pixel center.
+// Filters with negative lobes are sampled with a tent and weighted by filter / pdf.
+fn sample_filter(u: vec2<f32>) -> FilterSample {
+    var offset: vec2<f32> = u - 0.5;
+    var weight: f32 = 1.0;
+    if (uniforms.pixel_filter == FILTER_TENT) {
+        offset = vec2<f32>(sample_tent(u.x, 1.0), sample_tent(u.y, 1.0));
+    } elseif (uniforms.pixel_filter == FILTER_GAUSSIAN) {
+        let r = GAUSSIAN_SIGMA * sqrt(-2.0 * log(max(u.x, 0.0000001)));
+        let phi = 2.0 * PI * u.y;
+        offset = vec2<f32>(r * cos(phi), r * sin(phi));
+    } elseif (uniforms.pixel_filter == FILTER_MITCHELL) {
+        offset = vec2<f32>(sample_tent(u.x, 2.0), sample_tent(u.y, 2.0));
+        let pdf = tent_pdf(offset.x, 2.0) * tent_pdf(offset.y, 2.0);
+        weight = 0.0;
+        if (pdf > 0.0) {
+            weight = mitchell(offset.x) * mitchell(offset.y) / pdf;
+        }
+    }
+    return FilterSample(offset * uniforms.filter_width, weight);
+}
+
+fn jitter(d:vec3<f32>, phi:f32, sina:f32, cosa:f32) -> vec3<f32> {
+    let w = d;
+    let u = normalize(cross(w.yzx, w));
+    let v = cross(w, u);
+    return (u * cos(phi) + v * sin(phi)) * sina + w * cosa;
+}
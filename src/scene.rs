@@ -0,0 +1,479 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{camera::Camera, checkpoint, cornell_box, obj};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 3],
+}
+
+/// most materials of a scene including the one of the light, sizes the array in common.wgsl
+pub const MAX_MATERIALS: usize = 64;
+
+/// Diffuse material, `color` holds the albedo in rgb and the strength of the emission in a
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Material {
+    pub color: [f32; 4],
+}
+
+impl Material {
+    /// gray material of the cornell box and of meshes without one
+    pub const DEFAULT: Material = Material {
+        color: [0.7, 0.7, 0.7, 0.0],
+    };
+}
+
+/// Sphere light, the only light sampled by next event estimation.
+/// Emissive triangles only contribute when a path hits them
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light {
+    pub center: [f32; 3],
+    pub radius: f32,
+    pub material: Material,
+}
+
+impl Default for Light {
+    /// the light below the ceiling of the cornell box
+    fn default() -> Self {
+        Self {
+            center: [250.0, 500.0, 100.0],
+            radius: 10.0,
+            material: Material {
+                color: [1.0, 1.0, 1.0, 3.0],
+            },
+        }
+    }
+}
+
+/// Triangle geometry and materials rendered with the sphere light in compute.wgsl
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub vertices: Vec<Vertex>,
+    pub faces: Vec<[u32; 3]>,
+    /// index into `materials` for every face
+    pub face_materials: Vec<u32>,
+    pub materials: Vec<Material>,
+    pub light: Light,
+}
+
+impl Scene {
+    fn empty() -> Self {
+        Self {
+            vertices: Vec::new(),
+            faces: Vec::new(),
+            face_materials: Vec::new(),
+            materials: Vec::new(),
+            light: Light::default(),
+        }
+    }
+
+    pub fn cornell_box() -> Self {
+        Self {
+            vertices: cornell_box::VERTICES.to_vec(),
+            faces: cornell_box::FACES.to_vec(),
+            face_materials: vec![0; cornell_box::FACES.len()],
+            materials: vec![Material::DEFAULT],
+            light: Light::default(),
+        }
+    }
+
+    /// index of the material, equal materials are shared
+    fn add_material(&mut self, material: Material) -> u32 {
+        let index = match self.materials.iter().position(|m| *m == material) {
+            Some(index) => index,
+            None => {
+                self.materials.push(material);
+                self.materials.len() - 1
+            }
+        };
+        index as u32
+    }
+
+    /// adds the triangles and materials of another scene, the light is kept
+    pub fn append(&mut self, other: &Scene) {
+        let offset = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(&other.vertices);
+        self.faces.extend(
+            other
+                .faces
+                .iter()
+                .map(|f| [f[0] + offset, f[1] + offset, f[2] + offset]),
+        );
+        let materials: Vec<u32> = other
+            .materials
+            .iter()
+            .map(|&m| self.add_material(m))
+            .collect();
+        self.face_materials
+            .extend(other.face_materials.iter().map(|&m| materials[m as usize]));
+    }
+
+    /// Reads a scene file, a text file with one statement per line:
+    ///
+    /// ```text
+    /// # comment
+    /// cornell_box
+    /// material <name> <r> <g> <b> [<emission>]
+    /// mesh <file.obj> [scale <f>] [translate <x> <y> <z>] [material <name>]
+    /// light <x> <y> <z> <radius> [<emission>]
+    /// camera <x> <y> <z> <yaw in degrees> <pitch in degrees>
+    /// ```
+    ///
+    /// `cornell_box` adds the built in geometry, `mesh` the triangles of a Wavefront OBJ file
+    /// relative to the scene file. Materials have to be defined before they are used.
+    /// The faces of a mesh get its material, or the one of their `usemtl` statement if the
+    /// scene file defines a material of that name. .mtl files are not read.
+    /// `light` moves the sphere light, OBJ files are scenes of their own as well
+    pub fn load(path: &Path) -> Result<SceneFile, String> {
+        let read = |path: &Path| {
+            fs::read_to_string(path)
+                .map_err(|err| format!("failed to read {}: {}", path.display(), err))
+        };
+        let mut file = SceneFile {
+            scene: Scene::empty(),
+            camera: None,
+            files: vec![path.to_path_buf()],
+        };
+        let is_obj = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map_or(false, |e| e.eq_ignore_ascii_case("obj"));
+        if is_obj {
+            file.scene = load_mesh(&read(path)?, path, Material::DEFAULT, &HashMap::new())?;
+        } else {
+            let dir = path.parent().unwrap_or_else(|| Path::new("."));
+            let mut materials = HashMap::new();
+            for (number, line) in read(path)?.lines().enumerate() {
+                let error =
+                    |message: String| format!("{}:{}: {}", path.display(), number + 1, message);
+                let tokens: Vec<&str> = line.split_whitespace().collect();
+                match tokens.as_slice() {
+                    [] => {}
+                    [comment, ..] if comment.starts_with('#') => {}
+                    ["cornell_box"] => file.scene.append(&Scene::cornell_box()),
+                    ["material", name, values @ ..] => {
+                        let count = if values.len() == 4 { 4 } else { 3 };
+                        let v = parse_floats(values, count).map_err(error)?;
+                        let emission = v.get(3).copied().unwrap_or(0.);
+                        let material = Material {
+                            color: [v[0], v[1], v[2], emission],
+                        };
+                        materials.insert(name.to_string(), material);
+                    }
+                    ["mesh", mesh, options @ ..] => {
+                        let options = MeshOptions::parse(options).map_err(error)?;
+                        let material = match options.material {
+                            Some(name) => *materials
+                                .get(name)
+                                .ok_or_else(|| error(format!("unknown material '{}'", name)))?,
+                            None => Material::DEFAULT,
+                        };
+                        let mesh_path = dir.join(mesh);
+                        let mut mesh =
+                            load_mesh(&read(&mesh_path)?, &mesh_path, material, &materials)?;
+                        mesh.transform(options.scale, options.translation);
+                        file.scene.append(&mesh);
+                        file.files.push(mesh_path);
+                    }
+                    ["light", values @ ..] => {
+                        let count = if values.len() == 5 { 5 } else { 4 };
+                        let v = parse_floats(values, count).map_err(error)?;
+                        let emission = v.get(4).copied().unwrap_or(3.);
+                        file.scene.light = Light {
+                            center: [v[0], v[1], v[2]],
+                            radius: v[3],
+                            material: Material {
+                                color: [1.0, 1.0, 1.0, emission],
+                            },
+                        };
+                    }
+                    ["camera", values @ ..] => {
+                        let v = parse_floats(values, 5).map_err(error)?;
+                        file.camera = Some(Camera::new(
+                            (v[0], v[1], v[2]),
+                            cgmath::Deg(v[3]),
+                            cgmath::Deg(v[4]),
+                            cgmath::Deg(0.),
+                        ));
+                    }
+                    _ => return Err(error(format!("unknown statement '{}'", line.trim()))),
+                }
+            }
+        }
+        if file.scene.faces.is_empty() {
+            return Err(format!("{} contains no triangles", path.display()));
+        }
+        // the light has a material as well
+        if file.scene.materials.len() >= MAX_MATERIALS {
+            return Err(format!(
+                "{} uses more than {} materials",
+                path.display(),
+                MAX_MATERIALS - 1
+            ));
+        }
+        Ok(file)
+    }
+
+    /// scales the vertices, then moves them
+    fn transform(&mut self, scale: f32, translation: [f32; 3]) {
+        for vertex in self.vertices.iter_mut() {
+            for (p, t) in vertex.position.iter_mut().zip(translation.iter()) {
+                *p = *p * scale + t;
+            }
+        }
+    }
+
+    /// stable hash of the geometry, the materials and the light, see `checkpoint::hash`
+    pub fn hash(&self) -> u64 {
+        let light = &self.light;
+        let [x, y, z] = light.center;
+        checkpoint::hash(&[
+            bytemuck::cast_slice(&self.vertices),
+            bytemuck::cast_slice(&self.faces),
+            bytemuck::cast_slice(&self.face_materials),
+            bytemuck::cast_slice(&self.materials),
+            bytemuck::cast_slice(&[x, y, z, light.radius]),
+            bytemuck::cast_slice(&[light.material]),
+        ])
+    }
+}
+
+/// `scale`, `translate` and `material` options of a mesh statement
+struct MeshOptions<'a> {
+    scale: f32,
+    translation: [f32; 3],
+    material: Option<&'a str>,
+}
+
+impl<'a> MeshOptions<'a> {
+    fn parse(options: &[&'a str]) -> Result<Self, String> {
+        let mut parsed = MeshOptions {
+            scale: 1.,
+            translation: [0.; 3],
+            material: None,
+        };
+        let mut options = options;
+        while !options.is_empty() {
+            match options {
+                ["scale", value, rest @ ..] => {
+                    parsed.scale = parse_floats(&[value], 1)?[0];
+                    options = rest;
+                }
+                ["translate", x, y, z, rest @ ..] => {
+                    let v = parse_floats(&[x, y, z], 3)?;
+                    parsed.translation = [v[0], v[1], v[2]];
+                    options = rest;
+                }
+                ["material", name, rest @ ..] => {
+                    parsed.material = Some(name);
+                    options = rest;
+                }
+                _ => return Err(format!("invalid mesh options '{}'", options.join(" "))),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Scene read by `Scene::load` with the camera and the files it depends on
+pub struct SceneFile {
+    pub scene: Scene,
+    /// start position of the camera, if the file has one
+    pub camera: Option<Camera>,
+    /// the scene file and the meshes it references
+    pub files: Vec<PathBuf>,
+}
+
+impl SceneFile {
+    /// the scene file if there is one, otherwise the built in cornell box
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self, String> {
+        match path {
+            Some(path) => Scene::load(path),
+            None => Ok(Self {
+                scene: Scene::cornell_box(),
+                camera: Some(cornell_box::camera()),
+                files: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// the faces get `material` unless a `usemtl` statement names one of `materials`
+fn load_mesh(
+    source: &str,
+    path: &Path,
+    material: Material,
+    materials: &HashMap<String, Material>,
+) -> Result<Scene, String> {
+    let mesh = obj::parse(source).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut scene = Scene {
+        vertices: mesh.vertices,
+        ..Scene::empty()
+    };
+    let mut current = scene.add_material(material);
+    let mut usemtl = mesh.usemtl.iter().peekable();
+    for (i, face) in mesh.faces.into_iter().enumerate() {
+        while let Some((_, name)) = usemtl.next_if(|(first, _)| *first == i) {
+            current = scene.add_material(materials.get(name).copied().unwrap_or(material));
+        }
+        scene.faces.push(face);
+        scene.face_materials.push(current);
+    }
+    Ok(scene)
+}
+
+fn parse_floats(values: &[&str], count: usize) -> Result<Vec<f32>, String> {
+    let floats = values
+        .iter()
+        .map(|v| v.parse().ok())
+        .collect::<Option<Vec<f32>>>()
+        .filter(|floats| floats.len() == count);
+    floats.ok_or_else(|| format!("expected {} values, got '{}'", count, values.join(" ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+
+    /// directory in the temp directory which is removed when the test ends
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir = std::env::temp_dir().join(format!("rey-{}-{}", std::process::id(), name));
+            fs::create_dir_all(&dir).unwrap();
+            for (name, contents) in files {
+                fs::write(dir.join(name), contents).unwrap();
+            }
+            TempDir(dir)
+        }
+
+        fn load(&self, name: &str) -> Result<SceneFile, String> {
+            Scene::load(&self.0.join(name))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn loads_transformed_meshes() {
+        let dir = TempDir::new(
+            "transformed",
+            &[
+                ("triangle.obj", TRIANGLE),
+                (
+                    "scene.txt",
+                    "# two triangles\nmesh triangle.obj\n\nmesh triangle.obj scale 2 translate 1 2 3\n\
+                     camera 1 2 3 90 -10\n",
+                ),
+            ],
+        );
+        let file = dir.load("scene.txt").unwrap();
+        let scene = &file.scene;
+        let positions: Vec<[f32; 3]> = scene.vertices.iter().map(|v| v.position).collect();
+        assert_eq!(positions[3..], [[1., 2., 3.], [3., 2., 3.], [1., 4., 3.]]);
+        assert_eq!(scene.faces, [[0, 1, 2], [3, 4, 5]]);
+        assert!(file.camera.is_some());
+        assert_eq!(
+            file.files,
+            [
+                dir.0.join("scene.txt"),
+                dir.0.join("triangle.obj"),
+                dir.0.join("triangle.obj")
+            ]
+        );
+    }
+
+    #[test]
+    fn assigns_materials() {
+        let red = Material {
+            color: [1., 0., 0., 0.],
+        };
+        let lamp = Material {
+            color: [1., 1., 0.5, 4.],
+        };
+        let dir = TempDir::new(
+            "materials",
+            &[
+                (
+                    "mesh.obj",
+                    "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nusemtl lamp\nf 1 2 3\nusemtl other\nf 1 2 3\n",
+                ),
+                (
+                    "scene.txt",
+                    "material red 1 0 0\nmaterial lamp 1 1 0.5 4\ncornell_box\n\
+                     mesh mesh.obj material red\nlight 1 2 3 5 6\n",
+                ),
+            ],
+        );
+        let scene = dir.load("scene.txt").unwrap().scene;
+        assert_eq!(scene.materials, [Material::DEFAULT, red, lamp]);
+        let faces = scene.face_materials.len();
+        assert_eq!(scene.face_materials[faces - 3..], [1, 2, 1]);
+        assert!(scene.face_materials[..faces - 3].iter().all(|&m| m == 0));
+        let light = Light {
+            center: [1., 2., 3.],
+            radius: 5.,
+            material: Material {
+                color: [1., 1., 1., 6.],
+            },
+        };
+        assert_eq!(scene.light, light);
+    }
+
+    #[test]
+    fn loads_obj_files_as_scenes() {
+        let dir = TempDir::new("obj", &[("triangle.obj", TRIANGLE)]);
+        let scene = dir.load("triangle.obj").unwrap().scene;
+        assert_eq!(scene.faces, [[0, 1, 2]]);
+        assert_eq!(scene.face_materials, [0]);
+        assert_eq!(scene.materials, [Material::DEFAULT]);
+    }
+
+    #[test]
+    fn reports_invalid_scene_files() {
+        let dir = TempDir::new(
+            "invalid",
+            &[
+                ("triangle.obj", TRIANGLE),
+                ("empty.txt", "# nothing\n"),
+                ("unknown.txt", "cornell_box\nsphere 1 2 3\n"),
+                ("options.txt", "mesh triangle.obj scale\n"),
+                ("material.txt", "mesh triangle.obj material red\n"),
+            ],
+        );
+        let error = |name| dir.load(name).err().unwrap();
+        let path = |name| dir.0.join(name).display().to_string();
+        assert_eq!(
+            error("empty.txt"),
+            format!("{} contains no triangles", path("empty.txt"))
+        );
+        assert_eq!(
+            error("unknown.txt"),
+            format!(
+                "{}:2: unknown statement 'sphere 1 2 3'",
+                path("unknown.txt")
+            )
+        );
+        assert_eq!(
+            error("options.txt"),
+            format!("{}:1: invalid mesh options 'scale'", path("options.txt"))
+        );
+        assert_eq!(
+            error("material.txt"),
+            format!("{}:1: unknown material 'red'", path("material.txt"))
+        );
+    }
+}
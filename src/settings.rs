@@ -0,0 +1,758 @@
+use std::{path::PathBuf, str::FromStr, time::Duration};
+
+pub const USAGE: &str = "usage: rey [options]
+
+options:
+    --scene <file>         scene file or Wavefront OBJ to render instead of the cornell box,
+                           the viewer reloads it when it or its meshes change
+    --max-depth <n>        maximum number of bounces per path (default 3)
+    --min-distance <f>     minimum ray distance for intersections (default 0.001)
+    --rr-depth <n>         bounce at which russian roulette starts, 0 disables it (default 3)
+    --clamp <f>            clamp the radiance of a single sample (default unclamped)
+    --debug-view <name>    render a debug view instead of the shaded image: off, shading-normal,
+                           geometric-normal, albedo, depth, barycentrics, triangle-id,
+                           traversal-steps or sample-count (default off)
+    --sampler <name>       sample generator: random, sobol or bluenoise (default sobol)
+    --filter <name>        pixel filter: box, tent, gaussian or mitchell (default gaussian)
+    --filter-width <f>     scale of the pixel filter footprint (default 1)
+    --adaptive <f>         only sample pixels whose relative standard error is above this
+                           threshold, 0 disables adaptive sampling (default 0)
+    --adaptive-min-spp <n> samples every pixel gets before it can converge (default 16)
+    --architecture <name>  path tracer kernels: megakernel, or wavefront which runs every stage
+                           of the paths as its own dispatch (default megakernel)
+    --tonemap <name>       tone mapping operator: clamp, reinhard, aces, hable or agx (default clamp)
+    --exposure <ev>        exposure in stops (default 0)
+    --white-point <f>      smallest radiance mapped to white by reinhard (default 4)
+    --white-balance <k>    color temperature in kelvin mapped to white (default 6500)
+    --upscale <name>       filter of the display pass if the render resolution differs from the
+                           window: nearest or bilinear (default bilinear)
+    --render-scale <f>     render resolution relative to the window, 0.25 to 2 (default 1)
+    --render-size <w>x<h>  render resolution independent of the window size
+    --motion-scale <f>     scale of the render resolution while the camera moves, 1 disables it
+                           (default 0.5)
+    --frame-time <ms>      frame time the interactive view aims for while the camera rests,
+                           longer frames fit more samples between two presents (default 33)
+    --denoise <name>       denoiser: off, atrous or svgf (default off), offline renders use atrous
+    --denoise-iterations <n>
+                           number of wavelet iterations of the denoiser, at most 8 (default 5)
+    -o, --output <file>    render without a window and save the image, .exr files are stored linear
+    --spp <n>              stop after n samples per pixel
+    --time <s>             stop after s seconds
+    --max-error <f>        stop once the mean relative error of the pixels is below f,
+                           offline renders without a stop condition use --spp 64
+    --size <w>x<h>         resolution of the offline render (default 800x600)
+    --aovs                 save albedo, normal, depth, position and ids next to the image,
+                           as layers of .exr files or as separate <name>_<aov> images
+    --tile-size <n>        render the image in tiles of at most n x n pixels, images larger
+                           than the texture limit of the GPU use 2048 without it
+    --checkpoint <file>    save the accumulation of the offline render every minute and at the end
+    --resume <file>        continue an offline render from a checkpoint of the same scene,
+                           new checkpoints overwrite it unless --checkpoint is given
+    --oidn                 denoise the offline render with OpenImageDenoise,
+                           needs the oidn feature and falls back to --denoise without it
+    --benchmark            compare the speed of the architectures without a window,
+                           uses --size and --spp
+    --watch-shaders        reload the shaders of the viewer when their files in the source tree
+                           change, for development
+    -h, --help             print this message";
+
+/// Generator of the random numbers used in the shader,
+/// the values have to match the SAMPLER_* constants in common.wgsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampler {
+    Random = 0,
+    Sobol = 1,
+    BlueNoise = 2,
+}
+
+impl Sampler {
+    pub fn next(self) -> Self {
+        match self {
+            Sampler::Random => Sampler::Sobol,
+            Sampler::Sobol => Sampler::BlueNoise,
+            Sampler::BlueNoise => Sampler::Random,
+        }
+    }
+}
+
+impl FromStr for Sampler {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Sampler::Random),
+            "sobol" => Ok(Sampler::Sobol),
+            "bluenoise" => Ok(Sampler::BlueNoise),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Debug view rendered instead of the shaded image,
+/// the values have to match the DEBUG_* constants in common.wgsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugView {
+    Off = 0,
+    ShadingNormal = 1,
+    GeometricNormal = 2,
+    Albedo = 3,
+    Depth = 4,
+    Barycentrics = 5,
+    TriangleId = 6,
+    /// heat map of the primitive intersection tests of a path
+    TraversalSteps = 7,
+    /// heat map of the samples per pixel
+    SampleCount = 8,
+}
+
+impl DebugView {
+    pub fn next(self) -> Self {
+        match self {
+            DebugView::Off => DebugView::ShadingNormal,
+            DebugView::ShadingNormal => DebugView::GeometricNormal,
+            DebugView::GeometricNormal => DebugView::Albedo,
+            DebugView::Albedo => DebugView::Depth,
+            DebugView::Depth => DebugView::Barycentrics,
+            DebugView::Barycentrics => DebugView::TriangleId,
+            DebugView::TriangleId => DebugView::TraversalSteps,
+            DebugView::TraversalSteps => DebugView::SampleCount,
+            DebugView::SampleCount => DebugView::Off,
+        }
+    }
+}
+
+impl FromStr for DebugView {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(DebugView::Off),
+            "shading-normal" => Ok(DebugView::ShadingNormal),
+            "geometric-normal" => Ok(DebugView::GeometricNormal),
+            "albedo" => Ok(DebugView::Albedo),
+            "depth" => Ok(DebugView::Depth),
+            "barycentrics" => Ok(DebugView::Barycentrics),
+            "triangle-id" => Ok(DebugView::TriangleId),
+            "traversal-steps" => Ok(DebugView::TraversalSteps),
+            "sample-count" => Ok(DebugView::SampleCount),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Pixel reconstruction filter,
+/// the values have to match the FILTER_* constants in sampling.wgsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Box = 0,
+    Tent = 1,
+    Gaussian = 2,
+    Mitchell = 3,
+}
+
+impl Filter {
+    pub fn next(self) -> Self {
+        match self {
+            Filter::Box => Filter::Tent,
+            Filter::Tent => Filter::Gaussian,
+            Filter::Gaussian => Filter::Mitchell,
+            Filter::Mitchell => Filter::Box,
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::Mitchell),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Structure of the path tracer on the GPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Architecture {
+    /// one kernel traces the whole path of a sample
+    Megakernel,
+    /// the stages of the paths run as separate kernels over queues of the paths
+    Wavefront,
+}
+
+impl Architecture {
+    pub fn next(self) -> Self {
+        match self {
+            Architecture::Megakernel => Architecture::Wavefront,
+            Architecture::Wavefront => Architecture::Megakernel,
+        }
+    }
+}
+
+impl FromStr for Architecture {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "megakernel" => Ok(Architecture::Megakernel),
+            "wavefront" => Ok(Architecture::Wavefront),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Tone mapping operator of the display pass,
+/// the values have to match the TONEMAP_* constants in display.wgsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapper {
+    Clamp = 0,
+    Reinhard = 1,
+    Aces = 2,
+    Hable = 3,
+    AgX = 4,
+}
+
+impl ToneMapper {
+    pub fn next(self) -> Self {
+        match self {
+            ToneMapper::Clamp => ToneMapper::Reinhard,
+            ToneMapper::Reinhard => ToneMapper::Aces,
+            ToneMapper::Aces => ToneMapper::Hable,
+            ToneMapper::Hable => ToneMapper::AgX,
+            ToneMapper::AgX => ToneMapper::Clamp,
+        }
+    }
+}
+
+impl FromStr for ToneMapper {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(ToneMapper::Clamp),
+            "reinhard" => Ok(ToneMapper::Reinhard),
+            "aces" => Ok(ToneMapper::Aces),
+            "hable" => Ok(ToneMapper::Hable),
+            "agx" => Ok(ToneMapper::AgX),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Filter of the display pass when the render resolution differs from the window,
+/// the values have to match the UPSCALE_* constants in display.wgsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upscale {
+    Nearest = 0,
+    Bilinear = 1,
+}
+
+impl Upscale {
+    pub fn next(self) -> Self {
+        match self {
+            Upscale::Nearest => Upscale::Bilinear,
+            Upscale::Bilinear => Upscale::Nearest,
+        }
+    }
+}
+
+impl FromStr for Upscale {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Upscale::Nearest),
+            "bilinear" => Ok(Upscale::Bilinear),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Denoiser applied between the compute and the display pass,
+/// the values have to match the MODE_* constants in denoise.wgsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenoiseMode {
+    Off = 0,
+    /// edge-avoiding à-trous wavelet filter
+    ATrous = 1,
+    /// à-trous filter with temporal reprojection (spatiotemporal variance-guided filtering)
+    Svgf = 2,
+}
+
+impl DenoiseMode {
+    pub fn next(self) -> Self {
+        match self {
+            DenoiseMode::Off => DenoiseMode::ATrous,
+            DenoiseMode::ATrous => DenoiseMode::Svgf,
+            DenoiseMode::Svgf => DenoiseMode::Off,
+        }
+    }
+}
+
+impl FromStr for DenoiseMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(DenoiseMode::Off),
+            "atrous" => Ok(DenoiseMode::ATrous),
+            "svgf" => Ok(DenoiseMode::Svgf),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IntegratorSettings {
+    pub max_depth: u32,
+    pub min_distance: f32,
+    pub rr_depth: u32,
+    pub radiance_clamp: f32,
+    pub debug_view: DebugView,
+    pub sampler: Sampler,
+    pub filter: Filter,
+    pub filter_width: f32,
+    /// relative standard error below which a pixel is not sampled anymore, 0 disables it
+    pub adaptive_threshold: f32,
+    pub adaptive_min_samples: u32,
+    pub architecture: Architecture,
+}
+
+impl Default for IntegratorSettings {
+    fn default() -> Self {
+        Self {
+            max_depth: 3,
+            min_distance: 0.001,
+            rr_depth: 3,
+            radiance_clamp: 0.0,
+            debug_view: DebugView::Off,
+            sampler: Sampler::Sobol,
+            filter: Filter::Gaussian,
+            filter_width: 1.0,
+            adaptive_threshold: 0.0,
+            adaptive_min_samples: 16,
+            architecture: Architecture::Megakernel,
+        }
+    }
+}
+
+/// Settings of the denoise pass, changing them does not restart the accumulation
+#[derive(Debug, Clone, Copy)]
+pub struct DenoiseSettings {
+    pub mode: DenoiseMode,
+    /// number of à-trous iterations, the footprint doubles with every iteration
+    pub iterations: u32,
+    /// luminance edge stopping, in standard deviations of the noise
+    pub sigma_color: f32,
+    /// exponent of the normal edge stopping
+    pub sigma_normal: f32,
+    /// depth edge stopping, relative to the depth per step
+    pub sigma_depth: f32,
+    /// minimum weight of the current frame in the temporal accumulation
+    pub alpha_min: f32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            mode: DenoiseMode::Off,
+            iterations: 5,
+            sigma_color: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 0.01,
+            alpha_min: 0.2,
+        }
+    }
+}
+
+/// Settings of the display pass, changing them does not restart the accumulation
+#[derive(Debug, Clone, Copy)]
+pub struct DisplaySettings {
+    pub tone_mapper: ToneMapper,
+    /// exposure in stops
+    pub exposure: f32,
+    /// white point of the extended reinhard operator
+    pub white_point: f32,
+    /// color temperature in kelvin which is mapped to white
+    pub white_balance: f32,
+    pub upscale: Upscale,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            tone_mapper: ToneMapper::Clamp,
+            exposure: 0.0,
+            white_point: 4.0,
+            white_balance: 6500.0,
+            upscale: Upscale::Bilinear,
+        }
+    }
+}
+
+/// Resolution of the interactive render, independent of the window size
+#[derive(Debug, Clone, Copy)]
+pub struct ResolutionSettings {
+    /// render resolution relative to the window
+    pub scale: f32,
+    /// render resolution overriding the scale
+    pub size: Option<(u32, u32)>,
+    /// additional scale while the camera moves, 1 keeps the resolution
+    pub motion_scale: f32,
+}
+
+impl ResolutionSettings {
+    pub const MIN_SCALE: f32 = 0.25;
+    pub const MAX_SCALE: f32 = 2.0;
+
+    /// Resolution of the frame buffer for a window size. It is scaled down to fit
+    /// `max_dimension`, as larger textures fail the validation of the device
+    pub fn render_size(&self, window: (u32, u32), moving: bool, max_dimension: u32) -> (u32, u32) {
+        let (width, height) = match self.size {
+            Some(size) => size,
+            None => (
+                (window.0 as f32 * self.scale) as u32,
+                (window.1 as f32 * self.scale) as u32,
+            ),
+        };
+        let motion_scale = if moving { self.motion_scale } else { 1. };
+        let fit = (max_dimension as f32 / width.max(height) as f32).min(1.);
+        let scale = motion_scale * fit;
+        (
+            ((width as f32 * scale) as u32).max(1).min(max_dimension),
+            ((height as f32 * scale) as u32).max(1).min(max_dimension),
+        )
+    }
+}
+
+impl Default for ResolutionSettings {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            size: None,
+            motion_scale: 0.5,
+        }
+    }
+}
+
+/// Pacing of the interactive render
+#[derive(Debug, Clone, Copy)]
+pub struct PacingSettings {
+    /// the number of compute passes per frame adapts to it
+    pub target_frame_time: Duration,
+}
+
+impl Default for PacingSettings {
+    fn default() -> Self {
+        Self {
+            target_frame_time: Duration::from_millis(33),
+        }
+    }
+}
+
+/// Settings of the offline render, used when an output file is given
+#[derive(Debug, Clone)]
+pub struct OfflineSettings {
+    pub output: Option<PathBuf>,
+    pub width: u32,
+    pub height: u32,
+    /// save the AOVs as well
+    pub aovs: bool,
+    /// denoise the image with OpenImageDenoise, needs the oidn feature
+    pub oidn: bool,
+    /// maximum width and height of a tile, the image is rendered in one piece without tiles
+    pub tile_size: Option<u32>,
+    /// file the accumulation is saved to while rendering
+    pub checkpoint: Option<PathBuf>,
+    /// checkpoint the render continues from
+    pub resume: Option<PathBuf>,
+}
+
+impl Default for OfflineSettings {
+    fn default() -> Self {
+        Self {
+            output: None,
+            width: 800,
+            height: 600,
+            aovs: false,
+            oidn: false,
+            tile_size: None,
+            checkpoint: None,
+            resume: None,
+        }
+    }
+}
+
+/// Conditions which end the accumulation, the first one reached stops it.
+/// Without any condition the accumulation runs forever
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JobSettings {
+    /// samples per pixel
+    pub spp: Option<u32>,
+    pub time_limit: Option<Duration>,
+    /// mean relative standard error of the pixels
+    pub max_error: Option<f32>,
+}
+
+impl JobSettings {
+    pub fn is_bounded(&self) -> bool {
+        self.spp.is_some() || self.time_limit.is_some() || self.max_error.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// scene file, the cornell box is rendered without it
+    pub scene: Option<PathBuf>,
+    pub integrator: IntegratorSettings,
+    pub display: DisplaySettings,
+    pub denoise: DenoiseSettings,
+    pub resolution: ResolutionSettings,
+    pub pacing: PacingSettings,
+    pub offline: OfflineSettings,
+    pub job: JobSettings,
+    /// compare the architectures instead of rendering
+    pub benchmark: bool,
+    /// reload the shaders when they change on disk
+    pub watch_shaders: bool,
+}
+
+impl Settings {
+    /// parses the command line arguments (without the program name)
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut settings = Settings::default();
+        while let Some(arg) = args.next() {
+            let integrator = &mut settings.integrator;
+            let display = &mut settings.display;
+            let denoise = &mut settings.denoise;
+            let resolution = &mut settings.resolution;
+            let pacing = &mut settings.pacing;
+            let offline = &mut settings.offline;
+            let job = &mut settings.job;
+            match arg.as_str() {
+                "--scene" => settings.scene = Some(parse_value(&arg, args.next())?),
+                "--max-depth" => integrator.max_depth = parse_value(&arg, args.next())?,
+                "--min-distance" => integrator.min_distance = parse_positive(&arg, args.next())?,
+                "--rr-depth" => integrator.rr_depth = parse_value(&arg, args.next())?,
+                "--clamp" => integrator.radiance_clamp = parse_positive(&arg, args.next())?,
+                "--debug-view" => integrator.debug_view = parse_value(&arg, args.next())?,
+                "--sampler" => integrator.sampler = parse_value(&arg, args.next())?,
+                "--filter" => integrator.filter = parse_value(&arg, args.next())?,
+                "--filter-width" => integrator.filter_width = parse_positive(&arg, args.next())?,
+                "--adaptive" => integrator.adaptive_threshold = parse_value(&arg, args.next())?,
+                "--adaptive-min-spp" => {
+                    integrator.adaptive_min_samples = parse_value(&arg, args.next())?
+                }
+                "--architecture" => integrator.architecture = parse_value(&arg, args.next())?,
+                "--tonemap" => display.tone_mapper = parse_value(&arg, args.next())?,
+                "--exposure" => display.exposure = parse_value(&arg, args.next())?,
+                "--white-point" => display.white_point = parse_value(&arg, args.next())?,
+                "--white-balance" => display.white_balance = parse_value(&arg, args.next())?,
+                "--upscale" => display.upscale = parse_value(&arg, args.next())?,
+                "--render-scale" => {
+                    let scale: f32 = parse_value(&arg, args.next())?;
+                    if !(ResolutionSettings::MIN_SCALE..=ResolutionSettings::MAX_SCALE)
+                        .contains(&scale)
+                    {
+                        return Err(format!("invalid value '{}' for '{}'", scale, arg));
+                    }
+                    resolution.scale = scale;
+                }
+                "--render-size" => {
+                    let value: String = parse_value(&arg, args.next())?;
+                    let size = parse_size(&value)
+                        .ok_or_else(|| format!("invalid value '{}' for '{}'", value, arg))?;
+                    resolution.size = Some(size);
+                }
+                "--motion-scale" => {
+                    let scale: f32 = parse_value(&arg, args.next())?;
+                    if !(scale > 0. && scale <= 1.) {
+                        return Err(format!("invalid value '{}' for '{}'", scale, arg));
+                    }
+                    resolution.motion_scale = scale;
+                }
+                "--frame-time" => {
+                    let millis: f32 = parse_value(&arg, args.next())?;
+                    if !millis.is_finite() || millis <= 0. {
+                        return Err(format!("invalid value '{}' for '{}'", millis, arg));
+                    }
+                    pacing.target_frame_time = Duration::from_secs_f32(millis / 1000.);
+                }
+                "--denoise" => denoise.mode = parse_value(&arg, args.next())?,
+                "--denoise-iterations" => {
+                    denoise.iterations = parse_value::<u32>(&arg, args.next())?.max(1).min(8)
+                }
+                "-o" | "--output" => offline.output = Some(parse_value(&arg, args.next())?),
+                "--spp" => job.spp = Some(parse_value(&arg, args.next())?),
+                "--time" => {
+                    let seconds: f32 = parse_value(&arg, args.next())?;
+                    if !seconds.is_finite() || seconds < 0. {
+                        return Err(format!("invalid value '{}' for '{}'", seconds, arg));
+                    }
+                    job.time_limit = Some(Duration::from_secs_f32(seconds));
+                }
+                "--max-error" => job.max_error = Some(parse_positive(&arg, args.next())?),
+                "--size" => {
+                    let size: String = parse_value(&arg, args.next())?;
+                    let (width, height) = parse_size(&size)
+                        .ok_or_else(|| format!("invalid value '{}' for '{}'", size, arg))?;
+                    offline.width = width;
+                    offline.height = height;
+                }
+                "--aovs" => offline.aovs = true,
+                "--oidn" => offline.oidn = true,
+                "--tile-size" => {
+                    let size: u32 = parse_value(&arg, args.next())?;
+                    if size == 0 {
+                        return Err(format!("invalid value '{}' for '{}'", size, arg));
+                    }
+                    offline.tile_size = Some(size);
+                }
+                "--checkpoint" => offline.checkpoint = Some(parse_value(&arg, args.next())?),
+                "--resume" => offline.resume = Some(parse_value(&arg, args.next())?),
+                "--benchmark" => settings.benchmark = true,
+                "--watch-shaders" => settings.watch_shaders = true,
+                "-h" | "--help" => return Err(String::new()),
+                _ => return Err(format!("unknown argument '{}'", arg)),
+            }
+        }
+        let offline = &mut settings.offline;
+        if offline.output.is_none() && (offline.checkpoint.is_some() || offline.resume.is_some()) {
+            return Err("checkpoints are only supported by offline renders".to_string());
+        }
+        if offline.checkpoint.is_none() {
+            offline.checkpoint = offline.resume.clone();
+        }
+        if settings.offline.output.is_some() && !settings.job.is_bounded() {
+            settings.job.spp = Some(64);
+        }
+        Ok(settings)
+    }
+}
+
+fn parse_value<T: FromStr>(arg: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for '{}'", arg))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for '{}'", value, arg))
+}
+
+/// parses a finite value greater than zero
+fn parse_positive(arg: &str, value: Option<String>) -> Result<f32, String> {
+    let value: f32 = parse_value(arg, value)?;
+    if !value.is_finite() || value <= 0. {
+        return Err(format!("invalid value '{}' for '{}'", value, arg));
+    }
+    Ok(value)
+}
+
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let mut parts = size.split('x');
+    let width = parts.next()?.parse().ok()?;
+    let height = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Settings, String> {
+        Settings::from_args(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("800x600"), Some((800, 600)));
+        assert_eq!(parse_size("1x1"), Some((1, 1)));
+        for size in &[
+            "",
+            "800",
+            "800x",
+            "x600",
+            "0x600",
+            "800x0",
+            "800x600x1",
+            "-800x600",
+            "axb",
+        ] {
+            assert_eq!(parse_size(size), None, "{}", size);
+        }
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let settings = parse(
+            "--scene a.txt --max-depth 5 --sampler bluenoise --filter box --size 640x480 \
+             --spp 16 -o out.exr --aovs --tile-size 256",
+        )
+        .unwrap();
+        assert_eq!(settings.scene, Some(PathBuf::from("a.txt")));
+        assert_eq!(settings.integrator.max_depth, 5);
+        assert_eq!(settings.integrator.sampler, Sampler::BlueNoise);
+        assert_eq!(settings.integrator.filter, Filter::Box);
+        assert_eq!(
+            (settings.offline.width, settings.offline.height),
+            (640, 480)
+        );
+        assert_eq!(settings.job.spp, Some(16));
+        assert_eq!(settings.offline.output, Some(PathBuf::from("out.exr")));
+        assert!(settings.offline.aovs);
+        assert_eq!(settings.offline.tile_size, Some(256));
+    }
+
+    #[test]
+    fn parses_positive_values() {
+        let settings = parse("--clamp 10 --min-distance 0.01").unwrap();
+        assert!((settings.integrator.radiance_clamp - 10.).abs() < 1e-6);
+        assert!((settings.integrator.min_distance - 0.01).abs() < 1e-6);
+        for arg in &["--clamp", "--min-distance", "--filter-width", "--max-error"] {
+            for value in &["0", "-1", "inf", "NaN", "x"] {
+                assert!(
+                    parse(&format!("{} {}", arg, value)).is_err(),
+                    "{} {}",
+                    arg,
+                    value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        for args in &[
+            "--unknown",
+            "--max-depth",
+            "--max-depth -1",
+            "--sampler halton",
+            "--render-scale 4",
+            "--render-size 0x10",
+            "--motion-scale 0",
+            "--frame-time 0",
+            "--time -1",
+            "--tile-size 0",
+            "--checkpoint a.ckpt",
+            "--help",
+        ] {
+            assert!(parse(args).is_err(), "{}", args);
+        }
+    }
+
+    #[test]
+    fn bounds_offline_renders() {
+        assert_eq!(parse("").unwrap().job.spp, None);
+        assert_eq!(parse("-o out.png").unwrap().job.spp, Some(64));
+        let settings = parse("-o out.png --time 10").unwrap();
+        assert_eq!(settings.job.spp, None);
+        assert_eq!(settings.job.time_limit, Some(Duration::from_secs(10)));
+        let settings = parse("-o out.exr --resume a.ckpt").unwrap();
+        assert_eq!(settings.offline.checkpoint, Some(PathBuf::from("a.ckpt")));
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use crate::pipeline;
+
+/// WGSL files of the crate, compiled into the binary. Files can only include each other
+/// if they are listed here
+const BUILTIN: [(&str, &str); 9] = [
+    ("common.wgsl", include_str!("common.wgsl")),
+    ("sampling.wgsl", include_str!("sampling.wgsl")),
+    ("intersect.wgsl", include_str!("intersect.wgsl")),
+    ("integrator.wgsl", include_str!("integrator.wgsl")),
+    ("compute.wgsl", include_str!("compute.wgsl")),
+    ("wavefront.wgsl", include_str!("wavefront.wgsl")),
+    ("adaptive.wgsl", include_str!("adaptive.wgsl")),
+    ("denoise.wgsl", include_str!("denoise.wgsl")),
+    ("display.wgsl", include_str!("display.wgsl")),
+];
+
+/// names of the WGSL files of the crate
+pub fn file_names() -> impl Iterator<Item = &'static str> {
+    BUILTIN.iter().map(|(name, _)| *name)
+}
+
+/// Compile time constants and feature toggles of a shader variant.
+/// Every occurrence of a defined identifier is replaced by its value before the shader is
+/// compiled, so unlike WGSL constants they can size arrays and workgroups
+#[derive(Debug, Clone, Default)]
+pub struct Defines {
+    values: HashMap<String, String>,
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Defines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `value` is WGSL, e.g. `4` for array sizes, which naga only accepts without suffix
+    pub fn set(mut self, name: &str, value: impl ToString) -> Self {
+        assert!(is_identifier(name), "'{}' is no identifier", name);
+        self.values.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// unsigned constant for expressions and workgroup sizes
+    pub fn set_u32(self, name: &str, value: u32) -> Self {
+        self.set(name, format!("{}u", value))
+    }
+
+    /// feature toggle for `#ifdef`, in expressions it is `true`
+    pub fn enable(self, name: &str) -> Self {
+        self.set(name, "true")
+    }
+
+    /// adds the defines of another variant, its values win
+    pub fn with(mut self, other: &Defines) -> Self {
+        for (name, value) in other.values.iter() {
+            self.values.insert(name.clone(), value.clone());
+        }
+        self
+    }
+
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+
+    /// appends the line with the defined identifiers replaced
+    fn substitute(&self, line: &str, output: &mut String) {
+        let mut rest = line;
+        while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+            output.push_str(&rest[..start]);
+            let word = &rest[start..];
+            let end = word
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or_else(|| word.len());
+            let word = &word[..end];
+            // numbers can not be defined, so their suffixes are never replaced
+            output.push_str(self.values.get(word).map_or(word, |v| v.as_str()));
+            rest = &rest[start + end..];
+        }
+        output.push_str(rest);
+    }
+}
+
+/// Preprocessor of the WGSL files, resolves the directives
+///
+/// ```text
+/// #include "file.wgsl"
+/// #ifdef NAME / #ifndef NAME
+/// #else
+/// #endif
+/// ```
+///
+/// and replaces the defined identifiers. Every file is included once, later includes of it
+/// are skipped, so files include what they use
+#[derive(Debug, Clone, Default)]
+pub struct ShaderLibrary {
+    files: HashMap<String, Cow<'static, str>>,
+}
+
+impl ShaderLibrary {
+    /// the WGSL files compiled into the binary
+    pub fn builtin() -> Self {
+        let files = BUILTIN
+            .iter()
+            .map(|(name, source)| (name.to_string(), Cow::Borrowed(*source)))
+            .collect();
+        Self { files }
+    }
+
+    /// reads the WGSL files of the crate from a directory with the layout of the source tree
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let mut library = Self::default();
+        for name in file_names() {
+            let path = dir.join(name);
+            let source = fs::read_to_string(&path)
+                .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+            library.insert(name, source);
+        }
+        Ok(library)
+    }
+
+    /// adds or replaces a file
+    pub fn insert(&mut self, name: &str, source: impl Into<Cow<'static, str>>) {
+        self.files.insert(name.to_string(), source.into());
+    }
+
+    /// source of the shader `entry` with its includes resolved and the defines replaced
+    pub fn preprocess(&self, entry: &str, defines: &Defines) -> Result<String, String> {
+        let mut output = String::new();
+        self.include(entry, defines, &mut HashSet::new(), &mut output)?;
+        Ok(output)
+    }
+
+    /// the preprocessed shader as it is passed to wgpu
+    pub fn module<'a>(
+        &self,
+        label: &'a str,
+        entry: &str,
+        defines: &Defines,
+    ) -> Result<wgpu::ShaderModuleDescriptor<'a>, String> {
+        Ok(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(Cow::Owned(self.preprocess(entry, defines)?)),
+            flags: wgpu::ShaderFlags::VALIDATION,
+        })
+    }
+
+    /// preprocesses the shader and validates it with naga, the errors name the entry file
+    pub fn validate(&self, entry: &str, defines: &Defines) -> Result<(), String> {
+        let source = self.preprocess(entry, defines)?;
+        pipeline::validate_wgsl(&source).map_err(|err| format!("{}: {}", entry, err))
+    }
+
+    fn include(
+        &self,
+        name: &str,
+        defines: &Defines,
+        included: &mut HashSet<String>,
+        output: &mut String,
+    ) -> Result<(), String> {
+        // this also ends include cycles
+        if !included.insert(name.to_string()) {
+            return Ok(());
+        }
+        let source = self
+            .files
+            .get(name)
+            .ok_or_else(|| format!("unknown shader file '{}'", name))?;
+
+        // for every open #ifdef: its lines are used, an #else was seen
+        let mut conditions: Vec<(bool, bool)> = Vec::new();
+        for (number, line) in source.lines().enumerate() {
+            let error = |message: &str| format!("{}:{}: {}", name, number + 1, message);
+            let active = conditions.iter().all(|&(used, _)| used);
+            let directive = match line.trim_start().strip_prefix('#') {
+                Some(directive) => directive,
+                None => {
+                    if active {
+                        defines.substitute(line, output);
+                        output.push('\n');
+                    }
+                    continue;
+                }
+            };
+            let tokens: Vec<&str> = directive.split_whitespace().collect();
+            match tokens.as_slice() {
+                ["include", file] => {
+                    let file = file
+                        .strip_prefix('"')
+                        .and_then(|f| f.strip_suffix('"'))
+                        .ok_or_else(|| error("the included file has to be quoted"))?;
+                    if active {
+                        self.include(file, defines, included, output)?;
+                    }
+                }
+                ["ifdef", define] => conditions.push((defines.is_defined(define), false)),
+                ["ifndef", define] => conditions.push((!defines.is_defined(define), false)),
+                ["else"] => {
+                    let (used, has_else) = conditions
+                        .last_mut()
+                        .ok_or_else(|| error("#else without #ifdef"))?;
+                    if *has_else {
+                        return Err(error("second #else"));
+                    }
+                    *used = !*used;
+                    *has_else = true;
+                }
+                ["endif"] => {
+                    conditions
+                        .pop()
+                        .ok_or_else(|| error("#endif without #ifdef"))?;
+                }
+                _ => return Err(error(&format!("invalid directive '{}'", line.trim()))),
+            }
+        }
+        if !conditions.is_empty() {
+            return Err(format!("{}: #ifdef without #endif", name));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{adaptive, denoise, renderer, wavefront};
+
+    fn library(files: &[(&str, &'static str)]) -> ShaderLibrary {
+        let mut library = ShaderLibrary::default();
+        for (name, source) in files {
+            library.insert(name, *source);
+        }
+        library
+    }
+
+    #[test]
+    fn includes_every_file_once() {
+        let library = library(&[
+            (
+                "main.wgsl",
+                "#include \"a.wgsl\"\n#include \"b.wgsl\"\nmain\n",
+            ),
+            ("a.wgsl", "#include \"common.wgsl\"\na\n"),
+            (
+                "b.wgsl",
+                "#include \"common.wgsl\"\n#include \"main.wgsl\"\nb\n",
+            ),
+            ("common.wgsl", "common\n"),
+        ]);
+        let source = library.preprocess("main.wgsl", &Defines::new()).unwrap();
+        assert_eq!(source, "common\na\nb\nmain\n");
+    }
+
+    #[test]
+    fn replaces_defined_identifiers() {
+        let library = library(&[(
+            "main.wgsl",
+            "let x: array<u32, SIZE> = a.SIZE + SIZE_2 + 1SIZE;\n",
+        )]);
+        let defines = Defines::new().set("SIZE", 4).set_u32("SIZE_2", 8);
+        let source = library.preprocess("main.wgsl", &defines).unwrap();
+        assert_eq!(source, "let x: array<u32, 4> = a.4 + 8u + 1SIZE;\n");
+    }
+
+    #[test]
+    fn selects_the_branches_of_toggles() {
+        let library = library(&[(
+            "main.wgsl",
+            "#ifdef A\na\n#ifndef B\nnot b\n#else\nb\n#endif\n#else\nnot a\n#endif\n",
+        )]);
+        let preprocess = |defines| library.preprocess("main.wgsl", &defines).unwrap();
+        assert_eq!(preprocess(Defines::new()), "not a\n");
+        assert_eq!(preprocess(Defines::new().enable("A")), "a\nnot b\n");
+        assert_eq!(preprocess(Defines::new().enable("A").enable("B")), "a\nb\n");
+    }
+
+    #[test]
+    fn reports_invalid_directives() {
+        let error = |source: &'static str| {
+            library(&[("main.wgsl", source)])
+                .preprocess("main.wgsl", &Defines::new())
+                .unwrap_err()
+        };
+        assert_eq!(
+            error("#include \"missing.wgsl\""),
+            "unknown shader file 'missing.wgsl'"
+        );
+        assert_eq!(
+            error("#include main.wgsl"),
+            "main.wgsl:1: the included file has to be quoted"
+        );
+        assert_eq!(error("\n#endif"), "main.wgsl:2: #endif without #ifdef");
+        assert_eq!(error("#ifdef A\n"), "main.wgsl: #ifdef without #endif");
+        assert_eq!(
+            error("#define A 1"),
+            "main.wgsl:1: invalid directive '#define A 1'"
+        );
+    }
+
+    #[test]
+    fn builtin_shaders_validate() {
+        let library = ShaderLibrary::builtin();
+        let variants = [
+            ("compute.wgsl", renderer::path_tracer_defines()),
+            ("wavefront.wgsl", wavefront::defines()),
+            ("adaptive.wgsl", adaptive::defines()),
+            ("denoise.wgsl", denoise::defines()),
+            ("display.wgsl", Defines::new()),
+        ];
+        for (entry, defines) in variants.iter() {
+            if let Err(err) = library.validate(entry, defines) {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn library_files_validate_on_their_own() {
+        // every file includes what it uses
+        let library = ShaderLibrary::builtin();
+        let defines = renderer::path_tracer_defines();
+        for entry in [
+            "common.wgsl",
+            "sampling.wgsl",
+            "intersect.wgsl",
+            "integrator.wgsl",
+        ]
+        .iter()
+        {
+            if let Err(err) = library.validate(entry, &defines) {
+                panic!("{}", err);
+            }
+        }
+    }
+}
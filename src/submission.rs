@@ -0,0 +1,93 @@
+use std::{collections::VecDeque, future::Future, iter, pin::Pin, thread, time::Duration};
+
+use futures::FutureExt;
+use wgpu::util::DeviceExt;
+
+/// time slept between two polls while waiting for the oldest submission
+const POLL_INTERVAL: Duration = Duration::from_micros(250);
+
+/// Mapping of a tiny buffer written at the end of a submission,
+/// it resolves once the gpu finished the submission
+type Fence = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+/// Submits command buffers without waiting for the gpu, but keeps at most `depth` of them
+/// in flight so the cpu does not run ahead by more than a few frames.
+/// wgpu can only block until all submissions are done, so every submission gets a fence
+/// which is polled until the oldest one is finished
+pub struct SubmissionQueue {
+    depth: usize,
+    fence_source: wgpu::Buffer,
+    fences: VecDeque<(wgpu::Buffer, Fence)>,
+}
+
+impl SubmissionQueue {
+    pub fn new(device: &wgpu::Device, depth: usize) -> Self {
+        let fence_source = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("fence source"),
+            contents: &[0; 4],
+            usage: wgpu::BufferUsage::COPY_SRC,
+        });
+        Self {
+            depth: depth.max(1),
+            fence_source,
+            fences: VecDeque::new(),
+        }
+    }
+
+    /// submissions which the gpu has not finished yet
+    pub fn in_flight(&self) -> usize {
+        self.fences.len()
+    }
+
+    /// Submits the encoder and returns once no more than `depth` submissions are in flight.
+    /// Pending buffer mappings resolve meanwhile, as the device is polled
+    pub fn submit(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mut encoder: wgpu::CommandEncoder,
+    ) {
+        let fence = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("fence"),
+            size: 4,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_buffer_to_buffer(&self.fence_source, 0, &fence, 0, 4);
+        queue.submit(iter::once(encoder.finish()));
+
+        let mapping = Box::pin(fence.slice(..).map_async(wgpu::MapMode::Read));
+        self.fences.push_back((fence, mapping));
+        self.wait(device, self.depth);
+    }
+
+    /// blocks until all submissions are done
+    pub fn wait_idle(&mut self, device: &wgpu::Device) {
+        self.wait(device, 0);
+    }
+
+    /// polls the device until no more than `in_flight` submissions are pending
+    fn wait(&mut self, device: &wgpu::Device, in_flight: usize) {
+        loop {
+            device.poll(wgpu::Maintain::Poll);
+            self.retire();
+            if self.fences.len() <= in_flight {
+                return;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// drops the fences of the finished submissions
+    fn retire(&mut self) {
+        while let Some((_, mapping)) = self.fences.front_mut() {
+            match mapping.now_or_never() {
+                Some(result) => {
+                    result.expect("failed to map a fence");
+                    self.fences.pop_front();
+                }
+                None => return,
+            }
+        }
+    }
+}
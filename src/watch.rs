@@ -0,0 +1,53 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// time between two checks of the modification times
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Notices changes of files by comparing their modification times.
+/// Editors often replace a file instead of writing it, so missing files are no error
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_check: Instant,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl FileWatcher {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let time = modified(&path);
+                (path, time)
+            })
+            .collect();
+        Self {
+            files,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// true if any file changed since the last call, the files are checked at most
+    /// every CHECK_INTERVAL
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+        let mut changed = false;
+        for (path, time) in self.files.iter_mut() {
+            let new_time = modified(path);
+            if new_time.is_some() && new_time != *time {
+                *time = new_time;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
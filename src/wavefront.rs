@@ -0,0 +1,290 @@
+use wgpu::util::DeviceExt;
+
+use crate::{
+    indirect::{self, DispatchArgs},
+    pipeline, renderer,
+    shader::{Defines, ShaderLibrary},
+};
+
+/// paths in flight, larger frame buffers are rendered in several batches.
+/// The path states of a full pool take 80 MB
+const PATH_POOL_SIZE: u32 = 1 << 19;
+
+/// size of `PathState` in wavefront.wgsl
+const PATH_STATE_SIZE: u64 = 160;
+/// size of `Hit` in wavefront.wgsl
+const HIT_SIZE: u64 = 48;
+
+/// queues of wavefront.wgsl in the order of their QUEUE_* constants
+const NUM_QUEUES: u64 = 4;
+const QUEUE_EXTEND: u64 = 0;
+const QUEUE_DIFFUSE: u64 = 1;
+const QUEUE_EMISSIVE: u64 = 2;
+const QUEUE_CONNECT: u64 = 3;
+
+/// layout of the queue buffer header in u32, see wavefront.wgsl
+const HEADER_ARGS: u64 = 4;
+const HEADER_POOL_SIZE: usize = 17;
+const HEADER_SIZE: usize = 18;
+
+/// threads per workgroup of the path stages
+const PATH_GROUP_SIZE: u32 = 256;
+/// threads per workgroup and paths per thread of the compaction
+const CHUNK_GROUP_SIZE: u32 = 64;
+const CHUNK_SIZE: u32 = 256;
+
+/// constants of wavefront.wgsl and of the path tracer functions it includes
+pub(crate) fn defines() -> Defines {
+    renderer::path_tracer_defines()
+        .set_u32("NUM_QUEUES", NUM_QUEUES as u32)
+        .set_u32("QUEUE_EXTEND", QUEUE_EXTEND as u32)
+        .set_u32("QUEUE_DIFFUSE", QUEUE_DIFFUSE as u32)
+        .set_u32("QUEUE_EMISSIVE", QUEUE_EMISSIVE as u32)
+        .set_u32("QUEUE_CONNECT", QUEUE_CONNECT as u32)
+        .set_u32("HEADER_ARGS", HEADER_ARGS as u32)
+        .set_u32("HEADER_POOL_SIZE", HEADER_POOL_SIZE as u32)
+        .set_u32("HEADER_SIZE", HEADER_SIZE as u32)
+        .set_u32("PATH_GROUP_SIZE", PATH_GROUP_SIZE)
+        .set_u32("CHUNK_GROUP_SIZE", CHUNK_GROUP_SIZE)
+        .set_u32("CHUNK_SIZE", CHUNK_SIZE)
+}
+
+/// Path tracer split into stages which run as separate dispatches:
+/// generate camera rays, extend the paths to their next hit, shade the hits per material
+/// and connect them to the light with shadow rays. Between the stages the paths are sorted
+/// into queues, which size the indirect dispatch of the next stage.
+/// The result is the same as the one of the megakernel in compute.wgsl
+pub struct Wavefront {
+    begin_pipeline: wgpu::ComputePipeline,
+    generate_pipeline: wgpu::ComputePipeline,
+    extend_pipeline: wgpu::ComputePipeline,
+    shade_diffuse_pipeline: wgpu::ComputePipeline,
+    shade_emissive_pipeline: wgpu::ComputePipeline,
+    connect_pipeline: wgpu::ComputePipeline,
+    resolve_pipeline: wgpu::ComputePipeline,
+    count_pipeline: wgpu::ComputePipeline,
+    scan_pipeline: wgpu::ComputePipeline,
+    compact_pipeline: wgpu::ComputePipeline,
+
+    layout: wgpu::BindGroupLayout,
+    buffers: WavefrontBuffers,
+}
+
+/// size dependent resources of the wavefront path tracer
+struct WavefrontBuffers {
+    // only used through the bind group
+    _paths: wgpu::Buffer,
+    _hits: wgpu::Buffer,
+    queues: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pool_size: u32,
+}
+
+fn storage_buffer(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStage::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+impl Wavefront {
+    /// `layouts` are the frame buffer, uniform and scene layouts of the compute pass,
+    /// `pixel_list` is the one of the adaptive sampler.
+    /// `library` provides wavefront.wgsl and the path tracer functions it includes
+    pub fn new(
+        device: &wgpu::Device,
+        layouts: [&wgpu::BindGroupLayout; 3],
+        library: &ShaderLibrary,
+        pixel_list: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        // the pixel list has the same binding as in the megakernel
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("wavefront layout"),
+            entries: &[
+                storage_buffer(0, true),
+                storage_buffer(1, false),
+                storage_buffer(2, false),
+                storage_buffer(3, false),
+            ],
+        });
+
+        let pipeline = |entry_point, label| {
+            pipeline::create_compute_pipeline_with_entry(
+                device,
+                &[layouts[0], layouts[1], layouts[2], &layout],
+                library
+                    .module("wavefront_shader", "wavefront.wgsl", &defines())
+                    .expect("invalid wavefront shader"),
+                entry_point,
+                Some(label),
+            )
+        };
+        let begin_pipeline = pipeline("begin_batches", "WavefrontBeginPipeline");
+        let generate_pipeline = pipeline("generate", "WavefrontGeneratePipeline");
+        let extend_pipeline = pipeline("extend", "WavefrontExtendPipeline");
+        let shade_diffuse_pipeline = pipeline("shade_diffuse", "WavefrontShadeDiffusePipeline");
+        let shade_emissive_pipeline = pipeline("shade_emissive", "WavefrontShadeEmissivePipeline");
+        let connect_pipeline = pipeline("connect", "WavefrontConnectPipeline");
+        let resolve_pipeline = pipeline("resolve", "WavefrontResolvePipeline");
+        let count_pipeline = pipeline("count_queues", "WavefrontCountPipeline");
+        let scan_pipeline = pipeline("scan_queues", "WavefrontScanPipeline");
+        let compact_pipeline = pipeline("compact_queues", "WavefrontCompactPipeline");
+
+        let buffers = WavefrontBuffers::new(device, &layout, pixel_list, width * height);
+
+        Self {
+            begin_pipeline,
+            generate_pipeline,
+            extend_pipeline,
+            shade_diffuse_pipeline,
+            shade_emissive_pipeline,
+            connect_pipeline,
+            resolve_pipeline,
+            count_pipeline,
+            scan_pipeline,
+            compact_pipeline,
+
+            layout,
+            buffers,
+        }
+    }
+
+    /// the pixel list is recreated by the adaptive sampler on resize
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        pixel_list: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+    ) {
+        self.buffers = WavefrontBuffers::new(device, &self.layout, pixel_list, width * height);
+    }
+
+    /// Records the stages of one sample for each of `samples` pixels,
+    /// `bind_groups` are the frame buffer, uniform and scene bind groups of the compute pass.
+    /// With adaptive sampling `samples` is the upper bound of the pixel list
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_groups: [&wgpu::BindGroup; 3],
+        samples: u32,
+        max_depth: u32,
+    ) {
+        let pool_size = self.buffers.pool_size;
+        let path_groups = indirect::div_ceil(pool_size, PATH_GROUP_SIZE);
+        let chunk_groups =
+            indirect::div_ceil(indirect::div_ceil(pool_size, CHUNK_SIZE), CHUNK_GROUP_SIZE);
+        let queues = &self.buffers.queues;
+        let args = |queue: u64| HEADER_ARGS * 4 + queue * DispatchArgs::SIZE;
+
+        let mut c_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("wavefront"),
+        });
+        for (i, bind_group) in bind_groups.iter().enumerate() {
+            c_pass.set_bind_group(i as u32, bind_group, &[]);
+        }
+        c_pass.set_bind_group(3, &self.buffers.bind_group, &[]);
+
+        c_pass.set_pipeline(&self.begin_pipeline);
+        c_pass.dispatch(1, 1, 1);
+        for _ in 0..indirect::div_ceil(samples, pool_size) {
+            c_pass.set_pipeline(&self.generate_pipeline);
+            c_pass.dispatch(path_groups, 1, 1);
+            self.sort(&mut c_pass, chunk_groups);
+            for _ in 0..=max_depth {
+                c_pass.set_pipeline(&self.extend_pipeline);
+                c_pass.dispatch_indirect(queues, args(QUEUE_EXTEND));
+                self.sort(&mut c_pass, chunk_groups);
+                c_pass.set_pipeline(&self.shade_diffuse_pipeline);
+                c_pass.dispatch_indirect(queues, args(QUEUE_DIFFUSE));
+                c_pass.set_pipeline(&self.shade_emissive_pipeline);
+                c_pass.dispatch_indirect(queues, args(QUEUE_EMISSIVE));
+                self.sort(&mut c_pass, chunk_groups);
+                c_pass.set_pipeline(&self.connect_pipeline);
+                c_pass.dispatch_indirect(queues, args(QUEUE_CONNECT));
+            }
+            c_pass.set_pipeline(&self.resolve_pipeline);
+            c_pass.dispatch(path_groups, 1, 1);
+        }
+    }
+
+    /// rebuilds the queues from the flags of the paths
+    fn sort<'a>(&'a self, c_pass: &mut wgpu::ComputePass<'a>, chunk_groups: u32) {
+        c_pass.set_pipeline(&self.count_pipeline);
+        c_pass.dispatch(chunk_groups, 1, 1);
+        c_pass.set_pipeline(&self.scan_pipeline);
+        c_pass.dispatch(1, 1, 1);
+        c_pass.set_pipeline(&self.compact_pipeline);
+        c_pass.dispatch(chunk_groups, 1, 1);
+    }
+}
+
+impl WavefrontBuffers {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        pixel_list: &wgpu::Buffer,
+        pixels: u32,
+    ) -> Self {
+        let pool_size = pixels.min(PATH_POOL_SIZE).max(1);
+        let chunks = indirect::div_ceil(pool_size, CHUNK_SIZE) as u64;
+        let buffer = |label, size| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsage::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let paths = buffer("wavefront paths", PATH_STATE_SIZE * pool_size as u64);
+        let hits = buffer("wavefront hits", HIT_SIZE * pool_size as u64);
+
+        let queue_size = HEADER_SIZE + (NUM_QUEUES * (pool_size as u64 + chunks)) as usize;
+        let mut contents = vec![0u32; queue_size];
+        contents[HEADER_POOL_SIZE] = pool_size;
+        let queues = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("wavefront queues"),
+            contents: bytemuck::cast_slice(&contents),
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::INDIRECT,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("wavefront bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: pixel_list.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: paths.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: hits.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: queues.as_entire_binding(),
+                },
+            ],
+        });
+
+        Self {
+            _paths: paths,
+            _hits: hits,
+            queues,
+            bind_group,
+            pool_size,
+        }
+    }
+}
@@ -0,0 +1,435 @@
+// Wavefront formulation of lightColor: every stage of a path is its own entry point and the
+// paths waiting for a stage are kept in queues, so the threads of a dispatch run the same code.
+// The stages use the bindings and functions of the megakernel.
+//
+// naga has no atomics yet, so the queues are compacted with a prefix sum over chunks of paths
+// like the pixel list in adaptive.wgsl. The scan also writes the indirect dispatch arguments.
+//
+// The queue indices QUEUE_*, NUM_QUEUES, the header layout HEADER_ARGS, HEADER_POOL_SIZE and
+// HEADER_SIZE, and the sizes PATH_GROUP_SIZE, CHUNK_GROUP_SIZE and CHUNK_SIZE are defined by
+// wavefront.rs
+
+#include "integrator.wgsl"
+
+struct PathState {
+    // w: weight of the pixel filter sample
+    origin: vec4<f32>;
+    direction: vec4<f32>;
+    throughput: vec4<f32>;
+    radiance: vec4<f32>;
+    // xyz: contribution of the light if the shadow ray reaches it, w: cosine at the surface
+    shadow: vec4<f32>;
+    // attributes of the first hit, w: depth
+    first_normal: vec4<f32>;
+    // w: 1 if the front side was hit, -1 otherwise
+    first_position: vec4<f32>;
+    first_uv: vec2<f32>;
+    // material and object id, 0 if nothing was hit
+    first_ids: vec2<u32>;
+    // index of the frame buffer pixel
+    pixel: u32;
+    // state of the pcg sampler
+    seed: u32;
+    flags: u32;
+    // number of bounces so far
+    depth: u32;
+    traversal_steps: u32;
+};
+
+[[block]]
+struct Paths {
+    data: [[stride(160)]] array<PathState>;
+};
+
+[[group(3), binding(1)]]
+var<storage> paths: [[access(read_write)]] Paths;
+
+struct Hit {
+    // w: distance along the ray
+    position: vec4<f32>;
+    normal: vec4<f32>;
+    uv: vec2<f32>;
+    material: u32;
+    object: u32;
+};
+
+[[block]]
+struct Hits {
+    data: [[stride(48)]] array<Hit>;
+};
+
+// closest hit of the last extend stage
+[[group(3), binding(2)]]
+var<storage> hits: [[access(read_write)]] Hits;
+
+[[block]]
+struct Queues {
+    data: [[stride(4)]] array<u32>;
+};
+
+// header, followed by the queue entries and the offsets of the chunks in every queue
+[[group(3), binding(3)]]
+var<storage> queues: [[access(read_write)]] Queues;
+
+// the flags of a path have a bit per queue it is waiting in, 1 << QUEUE_*, and these ones:
+
+// no diffuse bounce yet, the emission is not sampled by a shadow ray
+let FLAG_SPECULAR: u32 = 16u;
+// the path belongs to a sample of the current batch
+let FLAG_SAMPLE: u32 = 32u;
+
+// the header holds the queue lengths, followed by the dispatch arguments of every queue at
+// HEADER_ARGS. Next is the index of the first sample of the batch
+let HEADER_BATCH: u32 = 16u;
+
+fn pool_size() -> u32 {
+    return queues.data[HEADER_POOL_SIZE];
+}
+
+fn num_chunks() -> u32 {
+    return (pool_size() + CHUNK_SIZE - 1u) / CHUNK_SIZE;
+}
+
+fn queue_entry(queue: u32, index: u32) -> u32 {
+    return HEADER_SIZE + queue * pool_size() + index;
+}
+
+fn chunk_offset(queue: u32, chunk: u32) -> u32 {
+    return HEADER_SIZE + NUM_QUEUES * pool_size() + queue * num_chunks() + chunk;
+}
+
+fn queue_bit(queue: u32) -> u32 {
+    return 1u << queue;
+}
+
+// path of the index-th thread of a dispatch over a queue, the pool size if there is none
+fn queued_path(queue: u32, index: u32) -> u32 {
+    if (index >= queues.data[queue]) {
+        return pool_size();
+    }
+    return queues.data[queue_entry(queue, index)];
+}
+
+// number of samples of the pass, they are split into batches of the pool size
+fn num_samples() -> u32 {
+    if (uniforms.adaptive != 0u) {
+        return pixel_list.count;
+    }
+    return uniforms.roi_size.x * uniforms.roi_size.y;
+}
+
+fn sample_pixel(sample: u32, size: vec2<u32>) -> vec2<u32> {
+    if (uniforms.adaptive != 0u) {
+        let p = pixel_list.data[sample];
+        return vec2<u32>(p % size.x, p / size.x);
+    }
+    let roi_width = uniforms.roi_size.x;
+    return vec2<u32>(sample % roi_width, sample / roi_width) + uniforms.roi_offset;
+}
+
+fn path_pixel(path: u32) -> vec2<u32> {
+    let size = vec2<u32>(textureDimensions(framebuffer_dst));
+    let p = paths.data[path].pixel;
+    return vec2<u32>(p % size.x, p / size.x);
+}
+
+// continues the random numbers of a path at the dimensions of its current bounce
+fn restore_sampler(path: u32) {
+    let image_pix = path_pixel(path) + uniforms.tile_offset;
+    pixel = image_pix;
+    pixel_seed = hash(image_pix.y * uniforms.image_size.x + image_pix.x);
+    seed = paths.data[path].seed;
+    start_bounce(paths.data[path].depth);
+}
+
+[[stage(compute), workgroup_size(1)]]
+fn begin_batches() {
+    queues.data[HEADER_BATCH] = 0u;
+}
+
+// starts a camera path for every sample of the batch
+[[stage(compute), workgroup_size(PATH_GROUP_SIZE)]]
+fn generate([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    let path = gid.x;
+    if (path >= pool_size()) {
+        return;
+    }
+    let sample = queues.data[HEADER_BATCH] + path;
+    if (sample >= num_samples()) {
+        paths.data[path].flags = 0u;
+        return;
+    }
+
+    let size = vec2<u32>(textureDimensions(framebuffer_dst));
+    let pix = sample_pixel(sample, size);
+    let image_pix = pix + uniforms.tile_offset;
+    let image_size = uniforms.image_size;
+    init_sampler(image_pix, image_size.x);
+
+    let c = Camera(1.0, f32(image_size.x) / f32(image_size.y));
+    let filter_sample = sample_filter(pixel_sample());
+    let uv = (vec2<f32>(image_pix) + 0.5 + filter_sample.offset) / vec2<f32>(image_size);
+    let ray = cast_ray_from_camera(c, uv);
+
+    let zero = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    paths.data[path].origin = vec4<f32>(ray.orig, filter_sample.weight);
+    paths.data[path].direction = vec4<f32>(ray.dir, 0.0);
+    paths.data[path].throughput = vec4<f32>(1.0, 1.0, 1.0, 0.0);
+    paths.data[path].radiance = zero;
+    paths.data[path].shadow = zero;
+    paths.data[path].first_normal = zero;
+    paths.data[path].first_position = zero;
+    paths.data[path].first_uv = vec2<f32>(0.0, 0.0);
+    paths.data[path].first_ids = vec2<u32>(0u, 0u);
+    paths.data[path].pixel = pix.y * size.x + pix.x;
+    paths.data[path].seed = seed;
+    paths.data[path].flags = queue_bit(QUEUE_EXTEND) | FLAG_SPECULAR | FLAG_SAMPLE;
+    paths.data[path].depth = 0u;
+    paths.data[path].traversal_steps = 0u;
+}
+
+// finds the closest hit of the paths and sorts them by the material
+[[stage(compute), workgroup_size(PATH_GROUP_SIZE)]]
+fn extend([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    let path = queued_path(QUEUE_EXTEND, gid.x);
+    if (path >= pool_size()) {
+        return;
+    }
+    let ray = Ray(paths.data[path].origin.xyz, paths.data[path].direction.xyz);
+    traversal_steps = paths.data[path].traversal_steps;
+    let hit = hitScene(ray);
+    paths.data[path].traversal_steps = traversal_steps;
+
+    var flags: u32 = paths.data[path].flags & ~queue_bit(QUEUE_EXTEND);
+    if (!hit) {
+        // TODO env map
+        paths.data[path].flags = flags;
+        return;
+    }
+    hits.data[path] = Hit(
+        vec4<f32>(intersec.pos, intersec.lambda),
+        vec4<f32>(intersec.normal, 0.0),
+        intersec.uv,
+        intersec.materialIdx,
+        intersec.objectIdx,
+    );
+
+    if (paths.data[path].depth == 0u) {
+        // ids start at 1 so that 0 means no hit
+        let forward = normalize((uniforms.u_view_proj * vec4<f32>(0.0, 0.0, 1.0, 0.0)).xyz);
+        let front = select(-1.0, 1.0, dot(intersec.normal, ray.dir) < 0.0);
+        paths.data[path].first_normal = vec4<f32>(intersec.normal, intersec.lambda * dot(ray.dir, forward));
+        paths.data[path].first_position = vec4<f32>(intersec.pos, front);
+        paths.data[path].first_uv = intersec.uv;
+        paths.data[path].first_ids = vec2<u32>(intersec.materialIdx + 1u, intersec.objectIdx + 1u);
+        // only the traversal steps need the whole path
+        if (uniforms.debug_view != DEBUG_OFF && uniforms.debug_view != DEBUG_TRAVERSAL_STEPS) {
+            paths.data[path].flags = flags;
+            return;
+        }
+    }
+
+    if (scene.materials[intersec.materialIdx].color.a > 0.0) {
+        flags = flags | queue_bit(QUEUE_EMISSIVE);
+    } else {
+        flags = flags | queue_bit(QUEUE_DIFFUSE);
+    }
+    paths.data[path].flags = flags;
+}
+
+// adds the emission of lights which were not sampled by a shadow ray, the paths end here
+[[stage(compute), workgroup_size(PATH_GROUP_SIZE)]]
+fn shade_emissive([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    let path = queued_path(QUEUE_EMISSIVE, gid.x);
+    if (path >= pool_size()) {
+        return;
+    }
+    let flags = paths.data[path].flags;
+    // the sphere light is sampled by the shadow rays, emissive faces only when they are hit
+    let hit = hits.data[path];
+    if ((flags & FLAG_SPECULAR) != 0u || hit.object >= num_spheres) {
+        let material = scene.materials[hit.material];
+        let throughput = paths.data[path].throughput.rgb;
+        let emission = material.color.rgb * material.color.a;
+        paths.data[path].radiance = paths.data[path].radiance + vec4<f32>(throughput * emission, 0.0);
+    }
+    paths.data[path].flags = flags & ~queue_bit(QUEUE_EMISSIVE);
+}
+
+// samples the next direction of the diffuse paths and prepares their shadow ray
+[[stage(compute), workgroup_size(PATH_GROUP_SIZE)]]
+fn shade_diffuse([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    let path = queued_path(QUEUE_DIFFUSE, gid.x);
+    if (path >= pool_size()) {
+        return;
+    }
+    restore_sampler(path);
+    let hit = hits.data[path];
+    let material = scene.materials[hit.material];
+    let depth = paths.data[path].depth;
+
+    let r2 = random();
+    let d = jitter(hit.normal.xyz, 2. * PI * random(), sqrt(r2), sqrt(1. - r2));
+
+    var throughput: vec3<f32> = paths.data[path].throughput.rgb * material.color.rgb;
+    paths.data[path].origin = vec4<f32>(hit.position.xyz, paths.data[path].origin.w);
+    paths.data[path].direction = vec4<f32>(d, 0.0);
+
+    let light = light_sphere();
+    let lightMaterial = scene.materials[light.materialIdx];
+    let lightDir = normalize(light.center - hit.position.xyz);
+    paths.data[path].shadow = vec4<f32>(
+        throughput * lightMaterial.color.rgb * lightMaterial.color.a,
+        dot(lightDir, hit.normal.xyz),
+    );
+
+    var flags: u32 = paths.data[path].flags & ~(queue_bit(QUEUE_DIFFUSE) | FLAG_SPECULAR);
+    flags = flags | queue_bit(QUEUE_CONNECT);
+
+    // russian roulette: terminate paths with low throughput
+    var alive: bool = depth < uniforms.max_depth;
+    if (uniforms.rr_depth > 0u && depth >= uniforms.rr_depth) {
+        let p = clamp(max(throughput.r, max(throughput.g, throughput.b)), 0.05, 1.0);
+        if (random() > p) {
+            alive = false;
+        }
+        throughput = throughput / p;
+    }
+    if (alive) {
+        flags = flags | queue_bit(QUEUE_EXTEND);
+    }
+
+    paths.data[path].throughput = vec4<f32>(throughput, 0.0);
+    paths.data[path].seed = seed;
+    paths.data[path].depth = depth + 1u;
+    paths.data[path].flags = flags;
+}
+
+// traces the shadow rays towards the light (next event estimation)
+[[stage(compute), workgroup_size(PATH_GROUP_SIZE)]]
+fn connect([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    let path = queued_path(QUEUE_CONNECT, gid.x);
+    if (path >= pool_size()) {
+        return;
+    }
+    let origin = paths.data[path].origin.xyz;
+    let light = light_sphere();
+    let lightDir = normalize(light.center - origin);
+    traversal_steps = paths.data[path].traversal_steps;
+    let hitAny = hitScene(Ray(origin, lightDir));
+    paths.data[path].traversal_steps = traversal_steps;
+
+    if (hitAny && intersec.objectIdx < num_spheres) {
+        let cos_a_max =
+            sqrt(1.0 - clamp(light.radius * light.radius / (intersec.lambda * intersec.lambda), 0.0, 1.0));
+        let weight = 2.0 * (1.0 - cos_a_max);
+        let shadow = paths.data[path].shadow;
+        paths.data[path].radiance = paths.data[path].radiance + vec4<f32>(shadow.xyz * (weight * shadow.w), 0.0);
+    }
+    paths.data[path].flags = paths.data[path].flags & ~queue_bit(QUEUE_CONNECT);
+}
+
+// adds the finished paths of the batch to the frame buffer
+[[stage(compute), workgroup_size(PATH_GROUP_SIZE)]]
+fn resolve([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    let path = gid.x;
+    if (path == 0u) {
+        // no other thread of this stage reads the batch
+        queues.data[HEADER_BATCH] = queues.data[HEADER_BATCH] + pool_size();
+    }
+    if (path >= pool_size() || (paths.data[path].flags & FLAG_SAMPLE) == 0u) {
+        return;
+    }
+    let state = paths.data[path];
+    var albedo: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
+    if (state.first_ids.x != 0u) {
+        albedo = scene.materials[state.first_ids.x - 1u].color.rgb;
+    }
+    first_hit = FirstHit(
+        albedo,
+        state.first_normal.xyz,
+        state.first_position.xyz,
+        state.first_normal.w,
+        state.first_ids.x,
+        state.first_ids.y,
+        state.first_uv,
+        state.first_normal.xyz * state.first_position.w,
+    );
+    traversal_steps = state.traversal_steps;
+
+    let pix = path_pixel(path);
+    accumulate(pix, pix + uniforms.tile_offset, state.radiance.rgb, state.origin.w);
+}
+
+// counts the paths of a chunk in every queue
+[[stage(compute), workgroup_size(CHUNK_GROUP_SIZE)]]
+fn count_queues([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    let chunk = gid.x;
+    if (chunk >= num_chunks()) {
+        return;
+    }
+    let end = min((chunk + 1u) * CHUNK_SIZE, pool_size());
+    var counts: vec4<u32> = vec4<u32>(0u, 0u, 0u, 0u);
+    for (var path: u32 = chunk * CHUNK_SIZE; path < end; path = path + 1u) {
+        let flags = paths.data[path].flags;
+        let bits = vec4<u32>(flags, flags, flags, flags) >> vec4<u32>(0u, 1u, 2u, 3u);
+        counts = counts + (bits & vec4<u32>(1u, 1u, 1u, 1u));
+    }
+    queues.data[chunk_offset(QUEUE_EXTEND, chunk)] = counts.x;
+    queues.data[chunk_offset(QUEUE_DIFFUSE, chunk)] = counts.y;
+    queues.data[chunk_offset(QUEUE_EMISSIVE, chunk)] = counts.z;
+    queues.data[chunk_offset(QUEUE_CONNECT, chunk)] = counts.w;
+}
+
+// exclusive prefix sum of the chunk counts of every queue, runs on a single thread
+[[stage(compute), workgroup_size(1)]]
+fn scan_queues() {
+    let chunks = num_chunks();
+    for (var queue: u32 = 0u; queue < NUM_QUEUES; queue = queue + 1u) {
+        var total: u32 = 0u;
+        for (var chunk: u32 = 0u; chunk < chunks; chunk = chunk + 1u) {
+            let index = chunk_offset(queue, chunk);
+            let count = queues.data[index];
+            queues.data[index] = total;
+            total = total + count;
+        }
+        queues.data[queue] = total;
+        let args = HEADER_ARGS + queue * 3u;
+        queues.data[args] = (total + PATH_GROUP_SIZE - 1u) / PATH_GROUP_SIZE;
+        queues.data[args + 1u] = 1u;
+        queues.data[args + 2u] = 1u;
+    }
+}
+
+// writes the paths of a chunk to the queues they are waiting in
+[[stage(compute), workgroup_size(CHUNK_GROUP_SIZE)]]
+fn compact_queues([[builtin(global_invocation_id)]] gid: vec3<u32>) {
+    let chunk = gid.x;
+    if (chunk >= num_chunks()) {
+        return;
+    }
+    var extend: u32 = queues.data[chunk_offset(QUEUE_EXTEND, chunk)];
+    var diffuse: u32 = queues.data[chunk_offset(QUEUE_DIFFUSE, chunk)];
+    var emissive: u32 = queues.data[chunk_offset(QUEUE_EMISSIVE, chunk)];
+    var connect: u32 = queues.data[chunk_offset(QUEUE_CONNECT, chunk)];
+    let end = min((chunk + 1u) * CHUNK_SIZE, pool_size());
+    for (var path: u32 = chunk * CHUNK_SIZE; path < end; path = path + 1u) {
+        let flags = paths.data[path].flags;
+        if ((flags & queue_bit(QUEUE_EXTEND)) != 0u) {
+            queues.data[queue_entry(QUEUE_EXTEND, extend)] = path;
+            extend = extend + 1u;
+        }
+        if ((flags & queue_bit(QUEUE_DIFFUSE)) != 0u) {
+            queues.data[queue_entry(QUEUE_DIFFUSE, diffuse)] = path;
+            diffuse = diffuse + 1u;
+        }
+        if ((flags & queue_bit(QUEUE_EMISSIVE)) != 0u) {
+            queues.data[queue_entry(QUEUE_EMISSIVE, emissive)] = path;
+            emissive = emissive + 1u;
+        }
+        if ((flags & queue_bit(QUEUE_CONNECT)) != 0u) {
+            queues.data[queue_entry(QUEUE_CONNECT, connect)] = path;
+            connect = connect + 1u;
+        }
+    }
+}